@@ -26,13 +26,13 @@ impl Bitmap {
     pub fn raster_line(&mut self, line: &[bool]) {
         let mut e = [0u8; 16];
 
-        if line.len() > self.width as usize {
+        if line.len() > self.width {
             panic!("Line width exceeds renderable width");
         }
 
-        for i in 0..line.len() {
+        for (i, v) in line.iter().enumerate() {
             // Skip unset pixels
-            if !line[i] {
+            if !v {
                 continue;
             }
 
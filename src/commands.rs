@@ -8,7 +8,7 @@ use std::time::Duration;
 
 use log::{trace, debug};
 
-use crate::{Error, PTouch, device::Status};
+use crate::{Error, device::Status};
 use crate::device::{AdvancedMode, Mode, PrintInfo, VariousMode, CompressionMode};
 
 /// Raw command API for the PTouch device.
@@ -51,7 +51,7 @@ pub trait Commands {
     fn set_page_no(&mut self, no: u8) -> Result<(), Error>;
 
     /// Set compression mode (None or Tiff).
-    /// Note TIFF mode is currently... broken
+    /// In TIFF mode raster data must be compressed with [`crate::tiff::compress`]
     fn set_compression_mode(&mut self, mode: CompressionMode) -> Result<(), Error>;
 
     /// Transfer raster data
@@ -67,22 +67,34 @@ pub trait Commands {
     fn print_and_feed(&mut self) -> Result<(), Error>;
 }
 
+/// Transport for exchanging commands and status with a PTouch device
+pub trait Transport {
+    /// Write command data with the provided timeout
+    fn write(&mut self, data: &[u8], timeout: Duration) -> Result<(), Error>;
+
+    /// Read a raw status message with the provided timeout
+    fn read(&mut self, timeout: Duration) -> Result<[u8; 32], Error>;
+
+    /// Fetch the default command timeout
+    fn timeout(&self) -> Duration;
+}
+
 /// Low-level command API implementation
-impl Commands for PTouch {
+impl <T: Transport> Commands for T {
     fn null(&mut self) -> Result<(), Error> {
-        self.write(&[0x00], self.timeout)
+        self.write(&[0x00], self.timeout())
     }
 
     fn init(&mut self) -> Result<(), Error> {
-        self.write(&[0x1b, 0x40], self.timeout)
+        self.write(&[0x1b, 0x40], self.timeout())
     }
 
     fn invalidate(&mut self) -> Result<(), Error> {
-        self.write(&[0u8; 100], self.timeout)
+        self.write(&[0u8; 100], self.timeout())
     }
 
     fn status_req(&mut self) -> Result<(), Error> {
-        self.write(&[0x1b, 0x69, 0x53], self.timeout)
+        self.write(&[0x1b, 0x69, 0x53], self.timeout())
     }
 
     fn read_status(&mut self, timeout: Duration) -> Result<Status, Error> {
@@ -97,7 +109,7 @@ impl Commands for PTouch {
     }
 
     fn switch_mode(&mut self, mode: Mode) -> Result<(), Error> {
-        self.write(&[0x1b, 0x69, 0x61, mode as u8], self.timeout)
+        self.write(&[0x1b, 0x69, 0x61, mode as u8], self.timeout())
     }
 
     fn set_status_notify(&mut self, enabled: bool) -> Result<(), Error> {
@@ -106,7 +118,7 @@ impl Commands for PTouch {
             false => 1,
         };
 
-        self.write(&[0x1b, 0x69, 0x21, en], self.timeout)
+        self.write(&[0x1b, 0x69, 0x21, en], self.timeout())
     }
 
     fn set_print_info(&mut self, info: &PrintInfo) -> Result<(), Error> {
@@ -126,34 +138,34 @@ impl Commands for PTouch {
 
         if let Some(w) = &info.width {
             buff[3] |= 0x04;
-            buff[5] = *w as u8;
+            buff[5] = *w;
         }
 
         if let Some(l) = &info.length {
             buff[3] |= 0x08;
-            buff[6] = *l as u8;
+            buff[6] = *l;
         }
 
         let raster_bytes = info.raster_no.to_le_bytes();
-        &buff[7..11].copy_from_slice(&raster_bytes);
+        buff[7..11].copy_from_slice(&raster_bytes);
 
         if info.recover {
             buff[3] |= 0x80;
         }
 
-        self.write(&buff, self.timeout)
+        self.write(&buff, self.timeout())
     }
 
     fn set_various_mode(&mut self, mode: VariousMode) -> Result<(), Error> {
         debug!("Set various mode: {:?}", mode);
 
-        self.write(&[0x1b, 0x69, 0x4d, mode.bits()], self.timeout)
+        self.write(&[0x1b, 0x69, 0x4d, mode.bits()], self.timeout())
     }
 
     fn set_advanced_mode(&mut self, mode: AdvancedMode) -> Result<(), Error> {
         debug!("Set advanced mode: {:?}", mode);
 
-        self.write(&[0x1b, 0x69, 0x4b, mode.bits()], self.timeout)
+        self.write(&[0x1b, 0x69, 0x4b, mode.bits()], self.timeout())
     }
 
     fn set_margin(&mut self, dots: u16) -> Result<(), Error> {
@@ -161,20 +173,20 @@ impl Commands for PTouch {
 
         self.write(
             &[0x1b, 0x69, 0x64, dots as u8, (dots >> 8) as u8],
-            self.timeout,
+            self.timeout(),
         )
     }
 
     fn set_page_no(&mut self, no: u8) -> Result<(), Error> {
         debug!("Set page no: {:?}", no);
 
-        self.write(&[0x1b, 0x69, 0x41, no], self.timeout)
+        self.write(&[0x1b, 0x69, 0x41, no], self.timeout())
     }
 
     fn set_compression_mode(&mut self, mode: CompressionMode) -> Result<(), Error> {
         debug!("Set compression mode: {:?}", mode);
 
-        self.write(&[0x4D, mode as u8], self.timeout)
+        self.write(&[0x4D, mode as u8], self.timeout())
     }
 
     fn raster_transfer(&mut self, data: &[u8]) -> Result<(), Error> {
@@ -184,26 +196,26 @@ impl Commands for PTouch {
         buff[1] = (data.len() & 0xFF) as u8;
         buff[2] = (data.len() >> 8) as u8;
 
-        (&mut buff[3..3+data.len()]).copy_from_slice(data);
+        buff[3..3+data.len()].copy_from_slice(data);
 
         trace!("Raster transfer: {:02x?}", &buff[..3+data.len()]);
 
-        self.write(&buff[..3+data.len()], self.timeout)
+        self.write(&buff[..3+data.len()], self.timeout())
     }
 
     fn raster_zero(&mut self) -> Result<(), Error> {
         debug!("Raster zero line");
         
-        self.write(&[0x5a], self.timeout)
+        self.write(&[0x5a], self.timeout())
     }
 
     fn print(&mut self) -> Result<(), Error> {
         debug!("Print command");
-        self.write(&[0x0c], self.timeout)
+        self.write(&[0x0c], self.timeout())
     }
 
     fn print_and_feed(&mut self) -> Result<(), Error> {
         debug!("Print feed command");
-        self.write(&[0x1a], self.timeout)
+        self.write(&[0x1a], self.timeout())
     }
 }
@@ -4,6 +4,8 @@
 // https://github.com/ryankurte/rust-ptouch
 // Copyright 2021 Ryan Kurte
 
+use std::ops::RangeInclusive;
+
 use bitflags::bitflags;
 
 #[cfg(feature = "strum")]
@@ -36,12 +38,65 @@ bitflags::bitflags! {
 pub enum PTouchDevice {
     #[cfg_attr(feature = "strum", strum(serialize = "pt-e550w"))]
     PtE550W = 0x2060,
+    #[cfg_attr(feature = "strum", strum(serialize = "pt-p700"))]
+    PtP700 = 0x2061,
     #[cfg_attr(feature = "strum", strum(serialize = "pt-p750w"))]
     PtP750W = 0x2062,
     #[cfg_attr(feature = "strum", strum(serialize = "pt-p710bt"))]
     PtP710Bt = 0x20af,
 }
 
+impl PTouchDevice {
+    /// Match a known device from a USB product ID
+    pub fn from_pid(pid: u16) -> Option<Self> {
+        use PTouchDevice::*;
+
+        match pid {
+            0x2060 => Some(PtE550W),
+            0x2061 => Some(PtP700),
+            0x2062 => Some(PtP750W),
+            0x20af => Some(PtP710Bt),
+            _ => None,
+        }
+    }
+
+    /// Fetch device capabilities
+    pub fn capabilities(&self) -> Capabilities {
+        use PTouchDevice::*;
+
+        let c = Capabilities {
+            width: 128,
+            tape_widths: &[6, 9, 12, 18, 24],
+            auto_cut: true,
+            half_cut: true,
+            compression: true,
+            feed: 0..=900,
+        };
+
+        match self {
+            PtP700 => Capabilities{ half_cut: false, ..c },
+            PtE550W | PtP750W | PtP710Bt => c,
+        }
+    }
+}
+
+
+/// Device capabilities
+#[derive(Clone, PartialEq, Debug)]
+pub struct Capabilities {
+    /// Print head width in pixels
+    pub width: usize,
+    /// Supported tape widths in mm
+    pub tape_widths: &'static [u8],
+    /// Automatic cutter
+    pub auto_cut: bool,
+    /// Half-cut (scoring the label backing)
+    pub half_cut: bool,
+    /// TIFF (PackBits) raster compression
+    pub compression: bool,
+    /// Supported feed (margin) range before and after each label, in dots
+    pub feed: RangeInclusive<u16>,
+}
 
 /// Media width encoding for Status message
 #[derive(Copy, Clone, PartialEq, Debug)]
@@ -96,6 +151,14 @@ impl From<(MediaKind, u8)> for Media {
     }
 }
 
+/// Fetch the printable height in pixels for a TZe tape width in mm
+pub fn tape_print_height(width_mm: u8) -> Option<usize> {
+    match Media::from((MediaKind::LaminatedTape, width_mm)) {
+        Media::Unknown => None,
+        m => Some(m.area().1),
+    }
+}
+
 impl Media {
     /// Fetch media print area (left margin, print area, right margin)
     pub fn area(&self) -> (usize, usize, usize) {
@@ -122,10 +185,7 @@ impl Media {
     pub fn is_tape(&self) -> bool {
         use Media::*;
 
-        match self {
-            Tze6mm | Tze9mm | Tze12mm | Tze18mm | Tze24mm => true,
-            _ => false,
-        }
+        matches!(self, Tze6mm | Tze9mm | Tze12mm | Tze18mm | Tze24mm)
     }
 
     /// Fetch the (approximate) media width in mm
@@ -326,7 +386,7 @@ impl From<u8> for TapeColour {
             0x91 => YellowFlexId,
             0xF0 => Cleaning,
             0xF1 => Stencil,
-            0xFF | _ => Incompatible,
+            _ => Incompatible,
         }
     }
 }
@@ -360,7 +420,7 @@ impl From<u8> for TextColour {
             0xf0 => Cleaning,
             0xF1 => Stencil,
             0x02 => Other,
-            0xFF | _=> Incompatible,
+            _ => Incompatible,
         }
     }
 }
@@ -368,6 +428,7 @@ impl From<u8> for TextColour {
 /// Device status message
 #[derive(Clone, PartialEq, Debug)]
 pub struct Status {
+    /// Model code
     pub model: u8,
 
     pub error1: Error1,
@@ -383,18 +444,25 @@ pub struct Status {
     pub text_colour: TextColour,
 }
 
+impl Status {
+    /// Fetch the loaded media
+    pub fn media(&self) -> Media {
+        Media::from((self.media_kind, self.media_width))
+    }
+}
+
 impl From<[u8; 32]> for Status {
 
     fn from(r: [u8; 32]) -> Self {
         Self {
-            model: r[0],
+            model: r[4],
             error1: Error1::from_bits_truncate(r[8]),
             error2: Error2::from_bits_truncate(r[9]),
             media_width: r[10],
             media_kind: MediaKind::from(r[11]),
 
             status_type: DeviceStatus::from(r[18]),
-            phase: Phase::from(r[20]),
+            phase: Phase::from(r[19]),
             tape_colour: TapeColour::from(r[24]),
             text_colour: TextColour::from(r[25]),
         }
@@ -434,3 +502,62 @@ pub enum CompressionMode {
     None = 0x00,
     Tiff = 0x02,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_status() {
+        // Status reply with 12mm laminated tape, printing, with the cover open
+        let r = [
+            0x80, 0x20, 0x42, 0x30, 0x76, 0x30, 0x00, 0x00,
+            0x00, 0x10, 0x0c, 0x01, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x02, 0x01, 0x00, 0x00, 0x00, 0x00,
+            0x01, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ];
+
+        assert_eq!(Status::from(r), Status {
+            model: 0x76,
+            error1: Error1::empty(),
+            error2: Error2::COVER_OPEN,
+            media_width: 12,
+            media_kind: MediaKind::LaminatedTape,
+            status_type: DeviceStatus::Error,
+            phase: Phase::Printing,
+            tape_colour: TapeColour::White,
+            text_colour: TextColour::Black,
+        });
+        assert_eq!(Status::from(r).media(), Media::Tze12mm);
+    }
+
+    #[test]
+    fn test_tape_print_height() {
+        let tests = &[
+            (6, Some(32)),
+            (9, Some(50)),
+            (12, Some(70)),
+            (18, Some(112)),
+            (24, Some(128)),
+            (36, None),
+        ];
+
+        for (w, h) in tests {
+            assert_eq!(tape_print_height(*w), *h, "{}mm", w);
+        }
+    }
+
+    #[test]
+    fn test_capabilities() {
+        use PTouchDevice::*;
+
+        for d in &[PtE550W, PtP700, PtP750W, PtP710Bt] {
+            let c = d.capabilities();
+            assert_eq!(c.width, 128, "{:?}", d);
+            assert_eq!(c.tape_widths.iter().filter_map(|w| tape_print_height(*w)).max(), Some(c.width), "{:?}", d);
+        }
+
+        assert!(!PtP700.capabilities().half_cut);
+        assert!(PtP710Bt.capabilities().half_cut);
+    }
+}
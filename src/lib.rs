@@ -3,12 +3,13 @@
 // https://github.com/ryankurte/rust-ptouch
 // Copyright 2021 Ryan Kurte
 
+use std::sync::{Arc, atomic::AtomicBool};
 use std::time::Duration;
 
-use commands::Commands;
+use commands::{Commands, Transport};
 use device::Status;
 use image::ImageError;
-use log::{trace, debug, error};
+use log::{trace, debug};
 
 #[cfg(feature = "structopt")]
 use structopt::StructOpt;
@@ -23,15 +24,23 @@ use device::*;
 
 pub mod commands;
 
+pub mod print;
+use print::PrintOptions;
+
+#[cfg(feature = "net")]
+pub mod net;
+
 pub mod bitmap;
 
 pub mod tiff;
 
 pub mod render;
+use render::Render;
 
 /// PTouch device instance
 pub struct PTouch {
     _device: Device<Context>,
+    kind: device::PTouchDevice,
     handle: DeviceHandle<Context>,
     descriptor: DeviceDescriptor,
     //endpoints: Endpoints,
@@ -39,6 +48,8 @@ pub struct PTouch {
 
     cmd_ep: u8,
     stat_ep: u8,
+
+    cancel: Arc<AtomicBool>,
 }
 
 /// Brother USB Vendor ID
@@ -59,6 +70,10 @@ pub struct Options {
     /// Index (if multiple devices are connected)
     pub index: usize,
 
+    #[cfg_attr(feature = "structopt", structopt(long))]
+    /// Serial number (to select between multiple connected devices)
+    pub serial: Option<String>,
+
     #[cfg_attr(feature = "structopt", structopt(long, hidden = true))]
     /// Do not reset the device on connect
     pub no_reset: bool,
@@ -103,6 +118,67 @@ pub enum Error {
     #[error("Renderer error")]
     Render,
 
+    #[error("Invalid render configuration: {0}")]
+    InvalidConfig(&'static str),
+
+    #[error("Render too wide ({needed} columns, maximum {max})")]
+    TooWide{ needed: usize, max: usize },
+
+    #[error("Render height ({render}px) does not match media print area ({media}px)")]
+    MediaSize{ render: usize, media: usize },
+
+    #[error("Print cancelled")]
+    Cancelled,
+
+    #[error("{0} is not supported by {1:?}")]
+    Unsupported(&'static str, device::PTouchDevice),
+
+    #[error("Feed of {0} dots outside the range supported by {1:?} ({2:?} dots)")]
+    Feed(u16, device::PTouchDevice, std::ops::RangeInclusive<u16>),
+
+    #[error("Unsupported image format: {0}")]
+    ImageFormat(String),
+
+    #[cfg(feature = "svg")]
+    #[error("SVG error: {0}")]
+    Svg(resvg::usvg::Error),
+
+    #[cfg(feature = "json")]
+    #[error("JSON error: {0}")]
+    Json(serde_json::Error),
+
+    #[cfg(feature = "toml")]
+    #[error("TOML error: {0}")]
+    Toml(String),
+
+    #[error("QR error: {0:?}")]
+    Qr(qrcode::types::QrError),
+
+    #[error("QR data does not fit in version {0} symbol")]
+    QrVersion(usize),
+
+    #[cfg(feature = "datamatrix")]
+    #[error("Data Matrix error: {0:?}")]
+    DataMatrix(datamatrix::data::DataEncodingError),
+
+    #[error("Barcode error: {0}")]
+    Barcode(barcoders::error::Error),
+
+    #[error("Invalid character '{0}' for {1} barcode")]
+    BarcodeCharacter(char, render::Symbology),
+
+    #[error("Invalid data length ({0}) for {1} barcode")]
+    BarcodeLength(usize, render::Symbology),
+
+    #[error("Invalid check digit for {0} barcode")]
+    BarcodeCheckDigit(render::Symbology),
+
+    #[error("Insufficient height ({0}px) for {1} barcode, minimum {2}px")]
+    BarcodeHeight(usize, render::Symbology, usize),
+
+    #[error("Unknown template variable: {0}")]
+    UnknownVariable(String),
+
     #[error("Operation timeout")]
     Timeout,
 
@@ -122,6 +198,53 @@ impl From<rusb::Error> for Error {
     }
 }
 
+#[cfg(feature = "svg")]
+impl From<resvg::usvg::Error> for Error {
+    fn from(e: resvg::usvg::Error) -> Self {
+        Error::Svg(e)
+    }
+}
+
+#[cfg(feature = "json")]
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Json(e)
+    }
+}
+
+#[cfg(feature = "toml")]
+impl From<toml::de::Error> for Error {
+    fn from(e: toml::de::Error) -> Self {
+        Error::Toml(e.to_string())
+    }
+}
+
+#[cfg(feature = "toml")]
+impl From<toml::ser::Error> for Error {
+    fn from(e: toml::ser::Error) -> Self {
+        Error::Toml(e.to_string())
+    }
+}
+
+impl From<qrcode::types::QrError> for Error {
+    fn from(e: qrcode::types::QrError) -> Self {
+        Error::Qr(e)
+    }
+}
+
+#[cfg(feature = "datamatrix")]
+impl From<datamatrix::data::DataEncodingError> for Error {
+    fn from(e: datamatrix::data::DataEncodingError) -> Self {
+        Error::DataMatrix(e)
+    }
+}
+
+impl From<barcoders::error::Error> for Error {
+    fn from(e: barcoders::error::Error) -> Self {
+        Error::Barcode(e)
+    }
+}
+
 impl From<ImageError> for Error {
     fn from(e: ImageError) -> Self {
         Error::Image(e)
@@ -136,6 +259,85 @@ pub struct Info {
     pub serial: String,
 }
 
+/// Connected PTouch printer information
+#[derive(Clone, Debug, PartialEq)]
+pub struct PrinterInfo {
+    /// Device kind
+    pub device: device::PTouchDevice,
+    /// Serial number (if readable)
+    pub serial: Option<String>,
+    /// USB bus number
+    pub bus: u8,
+    /// USB device address
+    pub address: u8,
+}
+
+/// List connected PTouch printers
+pub fn list_printers() -> Result<Vec<PrinterInfo>, Error> {
+    list_printers_with_context(&CONTEXT)
+}
+
+/// List connected PTouch printers using an existing rusb::Context
+pub fn list_printers_with_context(context: &Context) -> Result<Vec<PrinterInfo>, Error> {
+    let mut printers = vec![];
+
+    for d in context.devices()?.iter() {
+        // Fetch device descriptor
+        let desc = match d.device_descriptor() {
+            Ok(d) => d,
+            Err(e) => {
+                debug!("Could not fetch descriptor for device {:?}: {:?}", d, e);
+                continue;
+            }
+        };
+
+        // Filter for known devices
+        let device = match (desc.vendor_id(), PTouchDevice::from_pid(desc.product_id())) {
+            (BROTHER_VID, Some(p)) => p,
+            _ => continue,
+        };
+
+        let serial = read_serial(&d, &desc);
+
+        printers.push(PrinterInfo {
+            device,
+            serial,
+            bus: d.bus_number(),
+            address: d.address(),
+        });
+    }
+
+    Ok(printers)
+}
+
+/// Select the device at `index` from the list of matching devices
+fn select_device<T>(mut matches: Vec<T>, index: usize) -> Result<T, Error> {
+    // Check index is valid
+    if index >= matches.len() {
+        debug!(
+            "Device index ({}) exceeds number of discovered devices ({})",
+            index,
+            matches.len()
+        );
+        return Err(Error::InvalidIndex);
+    }
+
+    Ok(matches.remove(index))
+}
+
+/// Read a device serial number, this requires opening the device so may fail without permissions
+fn read_serial(d: &Device<Context>, desc: &DeviceDescriptor) -> Option<String> {
+    match d.open() {
+        Ok(h) => h.read_languages(DEFAULT_TIMEOUT).ok()
+            .and_then(|l| l.first().copied())
+            .and_then(|l| h.read_serial_number_string(l, desc, DEFAULT_TIMEOUT).ok()),
+        Err(e) => {
+            debug!("Could not open device {:?}: {:?}", d, e);
+            None
+        }
+    }
+}
+
 impl PTouch {
     /// Create a new PTouch driver with the provided USB options
     pub fn new(o: &Options) -> Result<Self, Error> {
@@ -169,23 +371,18 @@ impl PTouch {
             })
             .collect();
 
-        // Check index is valid
-        if matches.len() < o.index || matches.len() == 0 {
-            debug!(
-                "Device index ({}) exceeds number of discovered devices ({})",
-                o.index,
-                matches.len()
-            );
-            return Err(Error::InvalidIndex);
+        // Filter by serial number if provided
+        if let Some(serial) = &o.serial {
+            matches.retain(|(d, desc)| read_serial(d, desc).as_ref() == Some(serial));
         }
 
         debug!("Found matching devices: {:?}", matches);
 
         // Fetch matching device
-        let (device, descriptor) = matches.remove(o.index);
+        let (device, descriptor) = select_device(matches, o.index)?;
 
         // Open device handle
-        let mut handle = match device.open() {
+        let handle = match device.open() {
             Ok(v) => v,
             Err(e) => {
                 debug!("Error opening device");
@@ -273,11 +470,13 @@ impl PTouch {
         // Create device object
         let mut s = Self {
             _device: device,
+            kind: o.device,
             handle,
             descriptor,
             cmd_ep,
             stat_ep,
             timeout: DEFAULT_TIMEOUT,
+            cancel: Arc::new(AtomicBool::new(false)),
         };
 
         // Unless we're skipping reset
@@ -293,6 +492,21 @@ impl PTouch {
         Ok(s)
     }
 
+    /// Fetch the connected device kind
+    pub fn device(&self) -> PTouchDevice {
+        self.kind
+    }
+
+    /// Fetch a flag that cancels an in-progress print when set (from another thread)
+    pub fn cancel_flag(&self) -> Arc<AtomicBool> {
+        self.cancel.clone()
+    }
+
+    /// Cancel any in-progress print job, resetting the device
+    pub fn cancel(&mut self) -> Result<(), Error> {
+        print::reset(self)
+    }
+
     /// Fetch device information
     pub fn info(&mut self) -> Result<Info, Error> {
         let timeout = Duration::from_millis(200);
@@ -305,7 +519,7 @@ impl PTouch {
         trace!("Languages: {:?}", languages);
 
         // Check a language is available
-        if languages.len() == 0 {
+        if languages.is_empty() {
             return Err(Error::NoLanguages);
         }
 
@@ -344,87 +558,36 @@ impl PTouch {
         Ok(s)
     }
 
-    /// Setup the printer and print using raw raster data.
-    /// Print output must be shifted and in the correct bit-order for this function.
-    /// 
-    /// TODO: this is too low level of an interface, should be replaced with higher-level apis
-    pub fn print_raw(&mut self, data: Vec<[u8; 16]>, info: &PrintInfo) -> Result<(), Error> {
-        // TODO: should we check info (and size) match status here?
-
-
-        // Print sequence from raster guide Section 2.1
-        // 1. Set to raster mode
-        self.switch_mode(Mode::Raster)?;
-
-        // 2. Enable status notification
-        self.set_status_notify(true)?;
-
-        // 3. Set print information (media type etc.)
-        self.set_print_info(info)?;
-
-        // 4. Set various mode settings
-        self.set_various_mode(VariousMode::AUTO_CUT)?;
-
-        // 5. Specify page number in "cut each * labels"
-        // Note this is not supported on the PT-P710BT
-        // TODO: add this for other printers
-
-        // 6. Set advanced mode settings
-        self.set_advanced_mode(AdvancedMode::NO_CHAIN)?;
-
-        // 7. Specify margin amount
-        // TODO: based on what?
-        self.set_margin(0)?;
-
-        // 8. Set compression mode
-        // TODO: fix broken TIFF mode and add compression flag
-        self.set_compression_mode(CompressionMode::None)?;
-
-        // Send raster data
-        for line in data {
-            // TODO: re-add when TIFF mode issues resolved
-            //let l = tiff::compress(&line);
-
-            self.raster_transfer(&line)?;
-        }
-
-        // Execute print operation
-        self.print_and_feed()?;
-
-
-        // Poll on print completion
-        let mut i = 0;
-        loop {
-            if let Ok(s) = self.read_status(self.timeout) {
-                if !s.error1.is_empty() || !s.error2.is_empty() {
-                    debug!("Print error: {:?} {:?}", s.error1, s.error2);
-                    return Err(Error::PTouch(s.error1, s.error2));
-                }
-    
-                if s.status_type == DeviceStatus::PhaseChange {
-                    debug!("Started printing");
-                }
+    /// Print a rendered label, fitting output to the currently loaded media.
+    ///
+    /// The render height must match the media print area (see [`Media::area`]).
+    /// Note that USB access requires permission to open the device, on linux
+    /// this usually means a udev rule granting access to the Brother VID (`04f9`).
+    pub fn print(&mut self, render: &Render, opts: &PrintOptions) -> Result<(), Error> {
+        opts.validate(self.kind)?;
 
-                if s.status_type == DeviceStatus::Completed {
-                    debug!("Print completed");
-                    break;
-                }
-            }
-
-            if i > 10 {
-                debug!("Print timeout");
-                return Err(Error::Timeout);
-            }
+        print::print_renders(self, &[render], opts, &self.cancel.clone())
+    }
 
-            i += 1;
+    /// Print a batch of rendered labels, chaining them if enabled in `opts`
+    pub fn print_batch(&mut self, renders: &[Render], opts: &PrintOptions) -> Result<(), Error> {
+        opts.validate(self.kind)?;
 
-            std::thread::sleep(Duration::from_secs(1));
-        }
+        let renders: Vec<&Render> = renders.iter().collect();
+        print::print_renders(self, &renders, opts, &self.cancel.clone())
+    }
 
+    /// Setup the printer and print using raw raster data.
+    /// Print output must be shifted and in the correct bit-order for this function.
+    pub fn print_raw(&mut self, data: Vec<[u8; 16]>, info: &PrintInfo, opts: &PrintOptions) -> Result<(), Error> {
+        opts.validate(self.kind)?;
 
-        Ok(())
+        print::print_raster(self, &data, info, opts, &self.cancel.clone())
     }
+}
 
+/// USB transport for PTouch devices
+impl Transport for PTouch {
     /// Read from status EP (with specified timeout)
     fn read(&mut self, timeout: Duration) -> Result<[u8; 32], Error> {
         let mut buff = [0u8; 32];
@@ -446,7 +609,7 @@ impl PTouch {
         debug!("WRITE: {:02x?}", data);
 
         // Execute write
-        let n = self.handle.write_bulk(self.cmd_ep, &data, timeout)?;
+        let n = self.handle.write_bulk(self.cmd_ep, data, timeout)?;
 
         // Check write length for timeouts
         if n != data.len() {
@@ -455,4 +618,64 @@ impl PTouch {
 
         Ok(())
     }
+
+    fn timeout(&self) -> Duration {
+        self.timeout
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_device_pid() {
+        assert_eq!(PTouchDevice::from_pid(0x20af), Some(PTouchDevice::PtP710Bt));
+        assert_eq!(PTouchDevice::from_pid(0x2062), Some(PTouchDevice::PtP750W));
+        assert_eq!(PTouchDevice::from_pid(0x1234), None);
+    }
+
+    #[test]
+    fn test_select_device() {
+        assert_eq!(select_device(vec!["a", "b"], 1).unwrap(), "b");
+
+        // Index equal to (or beyond) the number of matches is an error
+        assert!(matches!(select_device(vec!["a"], 1), Err(Error::InvalidIndex)));
+        assert!(matches!(select_device(Vec::<&str>::new(), 0), Err(Error::InvalidIndex)));
+    }
+
+    /// Prints a label on a connected printer, set `PTOUCH_TEST_PRINT` to run
+    #[test]
+    fn test_print() {
+        if std::env::var("PTOUCH_TEST_PRINT").is_err() {
+            return;
+        }
+
+        let mut pt = PTouch::new(&Options{
+            device: PTouchDevice::PtP710Bt,
+            index: 0,
+            serial: None,
+            no_reset: false,
+            usb_no_claim: false,
+            usb_no_detach: false,
+        }).unwrap();
+
+        let media = pt.status().unwrap().media();
+
+        let mut r = Render::new(render::RenderConfig::default().with_media(&media));
+        r.render(&[render::Op::text("ptouch test")]).unwrap();
+
+        pt.print(&r, &PrintOptions::default()).unwrap();
+    }
+
+    /// Requires a connected printer, set `PTOUCH_TEST_DEVICE` to run
+    #[test]
+    fn test_list_printers() {
+        if std::env::var("PTOUCH_TEST_DEVICE").is_err() {
+            return;
+        }
+
+        let printers = list_printers().unwrap();
+        assert!(!printers.is_empty(), "no printers found");
+    }
 }
@@ -0,0 +1,160 @@
+//! Network (TCP) printing support
+// Rust PTouch Driver / Utility
+//
+// https://github.com/ryankurte/rust-ptouch
+// Copyright 2021 Ryan Kurte
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::{Arc, atomic::AtomicBool};
+use std::time::Duration;
+
+use log::debug;
+
+use crate::{DEFAULT_TIMEOUT, Error};
+use crate::commands::{Commands, Transport};
+use crate::device::{PTouchDevice, Status};
+use crate::print::{self, PrintOptions};
+use crate::render::Render;
+
+/// Default raw printing port
+pub const RAW_PORT: u16 = 9100;
+
+/// Network connected PTouch printer
+pub struct NetworkPrinter {
+    stream: TcpStream,
+    kind: PTouchDevice,
+    timeout: Duration,
+    cancel: Arc<AtomicBool>,
+}
+
+impl NetworkPrinter {
+    /// Connect to a network printer (usually on port [`RAW_PORT`])
+    pub fn connect(addr: SocketAddr, device: PTouchDevice) -> Result<Self, Error> {
+        debug!("Connecting to {:?} at {}", device, addr);
+
+        let stream = TcpStream::connect_timeout(&addr, DEFAULT_TIMEOUT)?;
+        stream.set_nodelay(true)?;
+
+        let mut s = Self {
+            stream,
+            kind: device,
+            timeout: DEFAULT_TIMEOUT,
+            cancel: Arc::new(AtomicBool::new(false)),
+        };
+
+        // Reset device
+        print::reset(&mut s)?;
+
+        Ok(s)
+    }
+
+    /// Fetch a flag that cancels an in-progress print when set (from another thread)
+    pub fn cancel_flag(&self) -> Arc<AtomicBool> {
+        self.cancel.clone()
+    }
+
+    /// Cancel any in-progress print job, resetting the device
+    pub fn cancel(&mut self) -> Result<(), Error> {
+        print::reset(self)
+    }
+
+    /// Fetch the device status
+    pub fn status(&mut self) -> Result<Status, Error> {
+        self.status_req()?;
+        self.read_status(self.timeout)
+    }
+
+    /// Print a rendered label, fitting output to the currently loaded media
+    pub fn print(&mut self, render: &Render, opts: &PrintOptions) -> Result<(), Error> {
+        opts.validate(self.kind)?;
+
+        print::print_renders(self, &[render], opts, &self.cancel.clone())
+    }
+
+    /// Print a batch of rendered labels, chaining them if enabled in `opts`
+    pub fn print_batch(&mut self, renders: &[Render], opts: &PrintOptions) -> Result<(), Error> {
+        opts.validate(self.kind)?;
+
+        let renders: Vec<&Render> = renders.iter().collect();
+        print::print_renders(self, &renders, opts, &self.cancel.clone())
+    }
+}
+
+/// TCP transport for network printers
+impl Transport for NetworkPrinter {
+    fn write(&mut self, data: &[u8], timeout: Duration) -> Result<(), Error> {
+        debug!("WRITE: {:02x?}", data);
+
+        self.stream.set_write_timeout(Some(timeout))?;
+        self.stream.write_all(data)?;
+
+        Ok(())
+    }
+
+    fn read(&mut self, timeout: Duration) -> Result<[u8; 32], Error> {
+        let mut buff = [0u8; 32];
+
+        self.stream.set_read_timeout(Some(timeout))?;
+        self.stream.read_exact(&mut buff)?;
+
+        Ok(buff)
+    }
+
+    fn timeout(&self) -> Duration {
+        self.timeout
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::TcpListener;
+
+    use super::*;
+    use crate::device::DeviceStatus;
+    use crate::render::{Op, RenderConfig};
+
+    #[test]
+    fn test_network_print() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // Mock printer reporting 12mm tape then print completion, capturing received bytes
+        let server = std::thread::spawn(move || {
+            let (mut s, _) = listener.accept().unwrap();
+
+            let mut media = [0u8; 32];
+            media[10] = 12;
+            media[11] = 0x01;
+
+            let mut complete = [0u8; 32];
+            complete[18] = DeviceStatus::Completed as u8;
+
+            s.write_all(&media).unwrap();
+            s.write_all(&complete).unwrap();
+
+            let mut rx = vec![];
+            s.read_to_end(&mut rx).unwrap();
+            rx
+        });
+
+        let mut r = Render::new(RenderConfig{ y: 70, ..Default::default() });
+        r.render(&[Op::text("net")]).unwrap();
+        let width = r.raster((29, 70, 29)).unwrap().len();
+
+        let mut p = NetworkPrinter::connect(addr, PTouchDevice::PtP750W).unwrap();
+        p.print(&r, &PrintOptions::default()).unwrap();
+        drop(p);
+
+        let rx = server.join().unwrap();
+
+        // Invalidate, init then status request
+        assert_eq!(&rx[..100], &[0u8; 100][..]);
+        assert_eq!(&rx[100..105], &[0x1b, 0x40, 0x1b, 0x69, 0x53]);
+
+        // One raster line per column, followed by print and feed
+        let rasters = rx.windows(3).filter(|w| w == &[0x47, 16, 0]).count();
+        assert_eq!(rasters, width);
+        assert_eq!(rx.last(), Some(&0x1a));
+    }
+}
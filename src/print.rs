@@ -0,0 +1,518 @@
+//! PTouch print sequence
+// Rust PTouch Driver / Utility
+//
+// https://github.com/ryankurte/rust-ptouch
+// Copyright 2021 Ryan Kurte
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use log::debug;
+
+#[cfg(feature = "structopt")]
+use structopt::StructOpt;
+
+use crate::{Error, tiff};
+use crate::render::Render;
+use crate::commands::{Commands, Transport};
+use crate::device::{AdvancedMode, CompressionMode, DeviceStatus, Mode, PrintInfo, PTouchDevice, VariousMode};
+
+/// Options for printing
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "structopt", derive(StructOpt))]
+pub struct PrintOptions {
+    #[cfg_attr(feature = "structopt", structopt(long = "no-auto-cut", parse(from_flag = std::ops::Not::not)))]
+    /// Disable cutting after printing
+    pub auto_cut: bool,
+
+    #[cfg_attr(feature = "structopt", structopt(long))]
+    /// Cut after every N labels (1-99, where supported)
+    pub cut_each: Option<u8>,
+
+    #[cfg_attr(feature = "structopt", structopt(long))]
+    /// Chain labels in a batch, feeding and cutting only after the last label
+    pub chain: bool,
+
+    #[cfg_attr(feature = "structopt", structopt(long))]
+    /// Half-cut (score the backing between labels, where supported)
+    pub half_cut: bool,
+
+    #[cfg_attr(feature = "structopt", structopt(long, default_value = "1"))]
+    /// Number of copies to print
+    pub copies: usize,
+
+    #[cfg_attr(feature = "structopt", structopt(long))]
+    /// Compress raster data (TIFF / PackBits)
+    pub compress: bool,
+
+    #[cfg_attr(feature = "structopt", structopt(long, default_value = "0"))]
+    /// Blank tape fed before each label, in dots
+    pub feed_before: u16,
+
+    #[cfg_attr(feature = "structopt", structopt(long, default_value = "0"))]
+    /// Blank tape fed after each label, in dots
+    pub feed_after: u16,
+}
+
+impl Default for PrintOptions {
+    fn default() -> Self {
+        Self {
+            auto_cut: true,
+            cut_each: None,
+            chain: false,
+            half_cut: false,
+            copies: 1,
+            compress: false,
+            feed_before: 0,
+            feed_after: 0,
+        }
+    }
+}
+
+impl PrintOptions {
+    /// Fetch various mode flags for these options
+    pub fn various_mode(&self) -> VariousMode {
+        match self.auto_cut {
+            true => VariousMode::AUTO_CUT,
+            false => VariousMode::empty(),
+        }
+    }
+
+    /// Fetch advanced mode flags for these options
+    pub fn advanced_mode(&self) -> AdvancedMode {
+        let mut mode = AdvancedMode::empty();
+
+        if !self.chain {
+            mode |= AdvancedMode::NO_CHAIN;
+        }
+
+        if self.half_cut {
+            mode |= AdvancedMode::HALF_CUT;
+        }
+
+        mode
+    }
+
+    /// Fetch the margin command amount and additional blank lines before and after each label.
+    ///
+    /// The margin command applies to both ends of a label, so the smaller feed
+    /// is used for the margin and the remainder is padded with blank raster lines.
+    pub fn margins(&self) -> (u16, usize, usize) {
+        let margin = self.feed_before.min(self.feed_after);
+
+        (margin, (self.feed_before - margin) as usize, (self.feed_after - margin) as usize)
+    }
+
+    /// Check options are supported by the provided device
+    pub fn validate(&self, device: PTouchDevice) -> Result<(), Error> {
+        let c = device.capabilities();
+
+        if (self.auto_cut || self.cut_each.is_some()) && !c.auto_cut {
+            return Err(Error::Unsupported("Auto-cut", device));
+        }
+
+        if let Some(n) = self.cut_each {
+            if !(1..=99).contains(&n) {
+                return Err(Error::InvalidConfig("cut each must be between 1 and 99 labels"));
+            }
+        }
+
+        if self.copies == 0 {
+            return Err(Error::InvalidConfig("copies must be non-zero"));
+        }
+
+        if self.half_cut && !c.half_cut {
+            return Err(Error::Unsupported("Half-cut", device));
+        }
+
+        if self.compress && !c.compression {
+            return Err(Error::Unsupported("Compression", device));
+        }
+
+        for feed in &[self.feed_before, self.feed_after] {
+            if !c.feed.contains(feed) {
+                return Err(Error::Feed(*feed, device, c.feed));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Print rendered labels on the loaded media, chaining them if enabled in `opts`
+pub fn print_renders<T: Transport>(t: &mut T, renders: &[&Render], opts: &PrintOptions, cancel: &AtomicBool) -> Result<(), Error> {
+    // Fetch loaded media
+    t.status_req()?;
+    let status = t.read_status(t.timeout())?;
+    let media = status.media();
+
+    // Generate raster data for each label
+    let data = renders.iter().map(|r| r.raster(media.area())).collect::<Result<Vec<_>, _>>()?;
+    let labels: Vec<&[[u8; 16]]> = data.iter().map(|d| d.as_slice()).collect();
+
+    // Setup print info based on media, raster lengths are set per label
+    let info = PrintInfo {
+        kind: Some(status.media_kind),
+        width: Some(status.media_width),
+        length: Some(0),
+        ..Default::default()
+    };
+
+    print_batch(t, &labels, &info, opts, cancel)
+}
+
+/// Setup the printer and print raw raster data.
+/// Print output must be shifted and in the correct bit-order for this function.
+pub fn print_raster<T: Transport>(t: &mut T, data: &[[u8; 16]], info: &PrintInfo, opts: &PrintOptions, cancel: &AtomicBool) -> Result<(), Error> {
+    print_batch(t, &[data], info, opts, cancel)
+}
+
+/// Setup the printer and print a batch of labels from raw raster data.
+///
+/// The batch is repeated `opts.copies` times. Each label is fed (and cut if
+/// enabled) after printing, unless `opts.chain` is set, in which case only
+/// the final label is fed.
+///
+/// Setting `cancel` aborts the print between raster lines, resetting the device
+/// and returning [`Error::Cancelled`].
+pub fn print_batch<T: Transport>(t: &mut T, labels: &[&[[u8; 16]]], info: &PrintInfo, opts: &PrintOptions, cancel: &AtomicBool) -> Result<(), Error> {
+    let labels: Vec<_> = labels.iter().cycle().take(labels.len() * opts.copies).collect();
+    let (margin, before, after) = opts.margins();
+
+    // TODO: should we check info (and size) match status here?
+
+    // Print sequence from raster guide Section 2.1
+    // 1. Set to raster mode
+    t.switch_mode(Mode::Raster)?;
+
+    // 2. Enable status notification
+    t.set_status_notify(true)?;
+
+    for (i, data) in labels.iter().enumerate() {
+        // Pad with blank lines for feed beyond the margin
+        let blank = [0u8; 16];
+        let lines = std::iter::repeat_n(&blank, before)
+            .chain(data.iter())
+            .chain(std::iter::repeat_n(&blank, after));
+
+        // 3. Set print information (media type etc.)
+        let info = PrintInfo {
+            raster_no: (before + data.len() + after) as u32,
+            ..info.clone()
+        };
+        t.set_print_info(&info)?;
+
+        // 4. Set various mode settings
+        t.set_various_mode(opts.various_mode())?;
+
+        // 5. Specify page number in "cut each * labels"
+        if let Some(n) = opts.cut_each {
+            t.set_page_no(n)?;
+        }
+
+        // 6. Set advanced mode settings
+        t.set_advanced_mode(opts.advanced_mode())?;
+
+        // 7. Specify margin amount
+        t.set_margin(margin)?;
+
+        // 8. Set compression mode
+        let mode = match opts.compress {
+            true => CompressionMode::Tiff,
+            false => CompressionMode::None,
+        };
+        t.set_compression_mode(mode)?;
+
+        // Send raster data
+        for line in lines {
+            if cancel.swap(false, Ordering::SeqCst) {
+                debug!("Print cancelled");
+                reset(t)?;
+                return Err(Error::Cancelled);
+            }
+
+            match opts.compress {
+                true => t.raster_transfer(&tiff::compress(line))?,
+                false => t.raster_transfer(line)?,
+            }
+        }
+
+        // Execute print operation, withholding feed until the last chained label
+        match opts.chain && i < labels.len() - 1 {
+            true => t.print()?,
+            false => t.print_and_feed()?,
+        }
+
+        wait_complete(t)?;
+    }
+
+    Ok(())
+}
+
+/// Reset the device, discarding any in-progress print job
+pub fn reset<T: Transport>(t: &mut T) -> Result<(), Error> {
+    // Send invalidate to clear buffered data
+    t.invalidate()?;
+    // Initialise device
+    t.init()
+}
+
+/// Poll on print completion
+fn wait_complete<T: Transport>(t: &mut T) -> Result<(), Error> {
+    let mut i = 0;
+    loop {
+        if let Ok(s) = t.read_status(t.timeout()) {
+            if !s.error1.is_empty() || !s.error2.is_empty() {
+                debug!("Print error: {:?} {:?}", s.error1, s.error2);
+                return Err(Error::PTouch(s.error1, s.error2));
+            }
+
+            if s.status_type == DeviceStatus::PhaseChange {
+                debug!("Started printing");
+            }
+
+            if s.status_type == DeviceStatus::Completed {
+                debug!("Print completed");
+                break;
+            }
+        }
+
+        if i > 10 {
+            debug!("Print timeout");
+            return Err(Error::Timeout);
+        }
+
+        i += 1;
+
+        std::thread::sleep(Duration::from_secs(1));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use std::sync::Arc;
+
+    use super::*;
+
+    /// Mock transport capturing written commands
+    pub struct MockTransport {
+        pub tx: Vec<Vec<u8>>,
+        /// Flag to set once the provided number of raster lines have been written
+        pub cancel_after: Option<(usize, Arc<AtomicBool>)>,
+    }
+
+    impl MockTransport {
+        pub fn new() -> Self {
+            Self{ tx: vec![], cancel_after: None }
+        }
+
+        /// Fetch the indices of written commands starting with the provided prefix
+        pub fn position(&self, prefix: &[u8]) -> Vec<usize> {
+            self.tx.iter().enumerate().filter(|(_, c)| c.starts_with(prefix)).map(|(i, _)| i).collect()
+        }
+
+        /// Find the first written command starting with the provided prefix
+        pub fn find(&self, prefix: &[u8]) -> Option<&Vec<u8>> {
+            self.tx.iter().find(|c| c.starts_with(prefix))
+        }
+    }
+
+    impl Transport for MockTransport {
+        fn write(&mut self, data: &[u8], _timeout: Duration) -> Result<(), Error> {
+            self.tx.push(data.to_vec());
+
+            if let Some((n, cancel)) = &self.cancel_after {
+                if data[0] == 0x47 && self.position(&[0x47]).len() == *n {
+                    cancel.store(true, Ordering::SeqCst);
+                }
+            }
+
+            Ok(())
+        }
+
+        fn read(&mut self, _timeout: Duration) -> Result<[u8; 32], Error> {
+            // Report print completion
+            let mut r = [0u8; 32];
+            r[18] = DeviceStatus::Completed as u8;
+            Ok(r)
+        }
+
+        fn timeout(&self) -> Duration {
+            Duration::from_millis(10)
+        }
+    }
+
+    #[test]
+    fn test_auto_cut() {
+        let data = vec![[0u8; 16]; 4];
+
+        // Auto cut enabled by default
+        let mut t = MockTransport::new();
+        print_raster(&mut t, &data, &PrintInfo::default(), &PrintOptions::default(), &AtomicBool::new(false)).unwrap();
+        assert_eq!(t.find(&[0x1b, 0x69, 0x4d]).unwrap(), &vec![0x1b, 0x69, 0x4d, 0x40]);
+        assert_eq!(t.find(&[0x1b, 0x69, 0x41]), None);
+
+        // Auto cut disabled
+        let mut t = MockTransport::new();
+        let opts = PrintOptions{ auto_cut: false, ..Default::default() };
+        print_raster(&mut t, &data, &PrintInfo::default(), &opts, &AtomicBool::new(false)).unwrap();
+        assert_eq!(t.find(&[0x1b, 0x69, 0x4d]).unwrap(), &vec![0x1b, 0x69, 0x4d, 0x00]);
+
+        // Cut every 3 labels
+        let mut t = MockTransport::new();
+        let opts = PrintOptions{ cut_each: Some(3), ..Default::default() };
+        print_raster(&mut t, &data, &PrintInfo::default(), &opts, &AtomicBool::new(false)).unwrap();
+        assert_eq!(t.find(&[0x1b, 0x69, 0x4d]).unwrap(), &vec![0x1b, 0x69, 0x4d, 0x40]);
+        assert_eq!(t.find(&[0x1b, 0x69, 0x41]).unwrap(), &vec![0x1b, 0x69, 0x41, 3]);
+
+        // Cut counts outside 1-99 are rejected
+        for n in &[0, 100, 200] {
+            let opts = PrintOptions{ cut_each: Some(*n), ..Default::default() };
+            assert!(matches!(opts.validate(PTouchDevice::PtP710Bt), Err(Error::InvalidConfig(_))));
+        }
+        assert!(PrintOptions{ cut_each: Some(99), ..Default::default() }.validate(PTouchDevice::PtP710Bt).is_ok());
+    }
+
+    #[test]
+    fn test_chain() {
+        let (a, b, c) = ([[0u8; 16]; 2], [[0u8; 16]; 3], [[0u8; 16]; 4]);
+        let labels: &[&[[u8; 16]]] = &[&a, &b, &c];
+
+        // Without chaining every label is fed
+        let mut t = MockTransport::new();
+        print_batch(&mut t, labels, &PrintInfo::default(), &PrintOptions::default(), &AtomicBool::new(false)).unwrap();
+        assert_eq!(t.position(&[0x1a]).len(), 3);
+        assert_eq!(t.position(&[0x0c]).len(), 0);
+        assert_eq!(t.find(&[0x1b, 0x69, 0x4b]).unwrap(), &vec![0x1b, 0x69, 0x4b, 0x08]);
+
+        // With chaining only the last label is fed
+        let mut t = MockTransport::new();
+        let opts = PrintOptions{ chain: true, ..Default::default() };
+        print_batch(&mut t, labels, &PrintInfo::default(), &opts, &AtomicBool::new(false)).unwrap();
+
+        let feeds = t.position(&[0x1a]);
+        let rasters = t.position(&[0x47]);
+        assert_eq!(feeds.len(), 1);
+        assert_eq!(t.position(&[0x0c]).len(), 2);
+        assert_eq!(rasters.len(), 9);
+        assert!(feeds[0] > *rasters.last().unwrap());
+        assert_eq!(t.find(&[0x1b, 0x69, 0x4b]).unwrap(), &vec![0x1b, 0x69, 0x4b, 0x00]);
+    }
+
+    #[test]
+    fn test_half_cut() {
+        let opts = PrintOptions{ half_cut: true, ..Default::default() };
+        assert_eq!(opts.advanced_mode(), AdvancedMode::NO_CHAIN | AdvancedMode::HALF_CUT);
+
+        // Rejected on devices without half-cut support
+        assert!(opts.validate(PTouchDevice::PtP710Bt).is_ok());
+        assert!(matches!(opts.validate(PTouchDevice::PtP700), Err(Error::Unsupported(_, PTouchDevice::PtP700))));
+        assert!(PrintOptions::default().validate(PTouchDevice::PtP700).is_ok());
+    }
+
+    #[test]
+    fn test_copies() {
+        let data = [[0xffu8; 16]; 2];
+
+        let mut t = MockTransport::new();
+        let opts = PrintOptions{ copies: 3, ..Default::default() };
+        print_raster(&mut t, &data, &PrintInfo::default(), &opts, &AtomicBool::new(false)).unwrap();
+
+        assert_eq!(t.position(&[0x47]).len(), 3 * 2);
+        assert_eq!(t.position(&[0x1b, 0x69, 0x7a]).len(), 3);
+        assert_eq!(t.position(&[0x1a]).len(), 3);
+
+        // Chained copies form a single strip
+        let mut t = MockTransport::new();
+        let opts = PrintOptions{ copies: 3, chain: true, ..Default::default() };
+        print_raster(&mut t, &data, &PrintInfo::default(), &opts, &AtomicBool::new(false)).unwrap();
+
+        assert_eq!(t.position(&[0x47]).len(), 3 * 2);
+        assert_eq!(t.position(&[0x0c]).len(), 2);
+        assert_eq!(t.position(&[0x1a]).len(), 1);
+
+        // At least one copy is required
+        let opts = PrintOptions{ copies: 0, ..Default::default() };
+        assert!(matches!(opts.validate(PTouchDevice::PtP710Bt), Err(Error::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn test_compress() {
+        let mut data = [[0u8; 16]; 2];
+        data[1][4] = 0xaa;
+
+        let mut t = MockTransport::new();
+        let opts = PrintOptions{ compress: true, ..Default::default() };
+        print_raster(&mut t, &data, &PrintInfo::default(), &opts, &AtomicBool::new(false)).unwrap();
+
+        assert_eq!(t.find(&[0x4d]).unwrap(), &vec![0x4d, 0x02]);
+
+        let rasters: Vec<_> = t.position(&[0x47]).iter().map(|i| t.tx[*i].clone()).collect();
+        assert_eq!(rasters, vec![
+            vec![0x47, 2, 0, 0xf1, 0x00],
+            vec![0x47, 6, 0, 0xfd, 0x00, 0x00, 0xaa, 0xf6, 0x00],
+        ]);
+    }
+
+    #[test]
+    fn test_cancel() {
+        let data = [[0u8; 16]; 8];
+        let cancel = Arc::new(AtomicBool::new(false));
+
+        let mut t = MockTransport::new();
+        t.cancel_after = Some((3, cancel.clone()));
+
+        let res = print_raster(&mut t, &data, &PrintInfo::default(), &PrintOptions::default(), &cancel);
+        assert!(matches!(res, Err(Error::Cancelled)));
+
+        // Raster loop stops early and the device is reset without printing
+        assert_eq!(t.position(&[0x47]).len(), 3);
+        assert_eq!(t.position(&[0x1a]).len(), 0);
+        assert_eq!(&t.tx[t.tx.len() - 2..], &[vec![0u8; 100], vec![0x1b, 0x40]]);
+
+        // Flag is cleared for subsequent prints
+        assert!(!cancel.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_margins() {
+        let data = [[0xffu8; 16]; 2];
+
+        let tests = &[
+            (0, 0, vec![0x1b, 0x69, 0x64, 0x00, 0x00], 0, 0),
+            (14, 14, vec![0x1b, 0x69, 0x64, 0x0e, 0x00], 0, 0),
+            (300, 20, vec![0x1b, 0x69, 0x64, 0x14, 0x00], 280, 0),
+            (256, 512, vec![0x1b, 0x69, 0x64, 0x00, 0x01], 0, 256),
+        ];
+
+        for (feed_before, feed_after, cmd, before, after) in tests {
+            let mut t = MockTransport::new();
+            let opts = PrintOptions{ feed_before: *feed_before, feed_after: *feed_after, ..Default::default() };
+            print_raster(&mut t, &data, &PrintInfo::default(), &opts, &AtomicBool::new(false)).unwrap();
+
+            assert_eq!(t.find(&[0x1b, 0x69, 0x64]).unwrap(), cmd);
+
+            // Remaining feed is padded with blank lines
+            let lines: Vec<_> = t.position(&[0x47]).iter().map(|i| t.tx[*i][3]).collect();
+            assert_eq!(lines.len(), before + 2 + after);
+            assert_eq!(lines.iter().position(|l| *l == 0xff), Some(*before));
+            assert_eq!(lines.iter().rposition(|l| *l == 0xff), Some(before + 1));
+        }
+    }
+
+    #[test]
+    fn test_feed_limits() {
+        let max = *PTouchDevice::PtP710Bt.capabilities().feed.end();
+
+        let opts = PrintOptions{ feed_before: max, feed_after: 14, ..Default::default() };
+        assert!(opts.validate(PTouchDevice::PtP710Bt).is_ok());
+
+        // Feeds outside the device range are rejected
+        for (before, after) in &[(max + 1, 0), (0, u16::MAX)] {
+            let opts = PrintOptions{ feed_before: *before, feed_after: *after, ..Default::default() };
+            assert!(matches!(opts.validate(PTouchDevice::PtP710Bt), Err(Error::Feed(v, PTouchDevice::PtP710Bt, _)) if v == (*before).max(*after)));
+        }
+    }
+}
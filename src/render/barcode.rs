@@ -0,0 +1,239 @@
+//! Barcode encoding support
+// Rust PTouch Driver / Utility
+//
+// https://github.com/ryankurte/rust-ptouch
+// Copyright 2021 Ryan Kurte
+
+use std::ops::Range;
+
+use crate::Error;
+use super::{FontKind, Symbology};
+
+/// Font used for human readable barcode text
+pub const BARCODE_FONT: FontKind = FontKind::Font6x8;
+
+/// Encoded module value for spaces
+pub const MODULE_SPACE: u8 = 0;
+/// Encoded module value for bars
+pub const MODULE_BAR: u8 = 1;
+/// Encoded module value for guard bars (extended below the data bars)
+pub const MODULE_GUARD: u8 = 2;
+
+/// EAN/UPC left-hand odd parity (L) digit encodings, 7 modules per digit
+const EAN_L: [u8; 10] = [
+    0b000_1101, 0b001_1001, 0b001_0011, 0b011_1101, 0b010_0011,
+    0b011_0001, 0b010_1111, 0b011_1011, 0b011_0111, 0b000_1011,
+];
+
+/// EAN-13 parity patterns for the left-hand digits, selected by the first digit (set bits use G encoding)
+const EAN_PARITY: [u8; 10] = [
+    0b00_0000, 0b00_1011, 0b00_1101, 0b00_1110, 0b01_0011,
+    0b01_1001, 0b01_1100, 0b01_0101, 0b01_0110, 0b01_1010,
+];
+
+/// Start and end guard pattern
+const EAN_GUARD: [u8; 3] = [1, 0, 1];
+
+/// Centre guard pattern
+const EAN_CENTRE: [u8; 5] = [0, 1, 0, 1, 0];
+
+/// EAN/UPC digit encoding sets
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum EanSet {
+    /// Left-hand odd parity
+    L,
+    /// Left-hand even parity
+    G,
+    /// Right-hand
+    R,
+}
+
+/// Append encoded modules for a single EAN/UPC digit
+fn ean_digit(modules: &mut Vec<u8>, set: EanSet, d: u8) {
+    let l = EAN_L[d as usize];
+
+    let (bits, reverse) = match set {
+        EanSet::L => (l, false),
+        EanSet::G => (!l & 0x7f, true),
+        EanSet::R => (!l & 0x7f, false),
+    };
+
+    for i in 0..7 {
+        let b = match reverse {
+            true => i,
+            false => 6 - i,
+        };
+        modules.push((bits >> b) & 1);
+    }
+}
+
+/// Append guard modules
+fn ean_guard(modules: &mut Vec<u8>, guard: &[u8]) {
+    modules.extend(guard.iter().map(|m| m * MODULE_GUARD));
+}
+
+/// Compute the modulo-10 check digit for EAN/UPC data digits
+pub fn ean_check_digit(digits: &[u8]) -> u8 {
+    // Weights alternate 3, 1 from the rightmost data digit
+    let sum: usize = digits.iter().rev().enumerate()
+        .map(|(i, d)| match i % 2 {
+            0 => *d as usize * 3,
+            _ => *d as usize,
+        }).sum();
+
+    ((10 - sum % 10) % 10) as u8
+}
+
+/// Parse EAN/UPC digits, computing the check digit if omitted or validating it if provided
+pub fn ean_digits(value: &str, len: usize, symbology: Symbology) -> Result<Vec<u8>, Error> {
+    if let Some(c) = value.chars().find(|c| !c.is_ascii_digit()) {
+        return Err(Error::BarcodeCharacter(c, symbology));
+    }
+
+    let mut digits: Vec<u8> = value.bytes().map(|b| b - b'0').collect();
+
+    match digits.len() {
+        l if l == len - 1 => digits.push(ean_check_digit(&digits)),
+        l if l == len => if digits[len - 1] != ean_check_digit(&digits[..len - 1]) {
+            return Err(Error::BarcodeCheckDigit(symbology));
+        },
+        l => return Err(Error::BarcodeLength(l, symbology)),
+    }
+
+    Ok(digits)
+}
+
+/// Encode EAN/UPC modules from left and right halves, using `parity` to select L/G sets for left digits
+fn ean_modules(left: &[u8], parity: u8, right: &[u8]) -> Vec<u8> {
+    let mut modules = Vec::with_capacity(11 + 7 * (left.len() + right.len()));
+
+    ean_guard(&mut modules, &EAN_GUARD);
+
+    for (i, d) in left.iter().enumerate() {
+        let set = match parity & (1 << (left.len() - 1 - i)) != 0 {
+            true => EanSet::G,
+            false => EanSet::L,
+        };
+        ean_digit(&mut modules, set, *d);
+    }
+
+    ean_guard(&mut modules, &EAN_CENTRE);
+
+    for d in right {
+        ean_digit(&mut modules, EanSet::R, *d);
+    }
+
+    ean_guard(&mut modules, &EAN_GUARD);
+
+    modules
+}
+
+/// Encode an EAN-13 barcode from 12 (or 13 with check digit) digits
+pub fn ean13(value: &str) -> Result<Vec<u8>, Error> {
+    let digits = ean_digits(value, 13, Symbology::Ean13)?;
+
+    // Leading digit is encoded in the parity of the left half
+    Ok(ean_modules(&digits[1..7], EAN_PARITY[digits[0] as usize], &digits[7..]))
+}
+
+/// Encode an EAN-8 barcode from 7 (or 8 with check digit) digits
+pub fn ean8(value: &str) -> Result<Vec<u8>, Error> {
+    let digits = ean_digits(value, 8, Symbology::Ean8)?;
+
+    Ok(ean_modules(&digits[..4], 0, &digits[4..]))
+}
+
+/// Encode a UPC-A barcode from 11 (or 12 with check digit) digits
+pub fn upca(value: &str) -> Result<Vec<u8>, Error> {
+    let digits = ean_digits(value, 12, Symbology::UpcA)?;
+
+    Ok(ean_modules(&digits[..6], 0, &digits[6..]))
+}
+
+/// Split human readable barcode text into segments, each centred over a range of modules
+pub fn human_readable(value: &str, symbology: Symbology, modules: usize) -> Result<Vec<(String, Range<usize>)>, Error> {
+    let len = match symbology {
+        Symbology::Ean13 => 13,
+        Symbology::Ean8 => 8,
+        Symbology::UpcA => 12,
+        _ => return Ok(vec![(value.to_string(), 0..modules)]),
+    };
+
+    // Retail digits (including the check digit) are split either side of the centre guard
+    let digits: String = ean_digits(value, len, symbology)?.iter().map(|d| (b'0' + d) as char).collect();
+    let (left, right) = digits.split_at(len / 2 + len % 2);
+    let half = (modules - 11) / 2;
+
+    Ok(vec![
+        (left.to_string(), 3..3 + half),
+        (right.to_string(), 8 + half..modules - 3),
+    ])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn modules_str(m: &[u8]) -> String {
+        m.iter().map(|v| match *v != MODULE_SPACE {
+            true => '1',
+            false => '0',
+        }).collect()
+    }
+
+    #[test]
+    fn test_ean13() {
+        let expected = "10100010110100111011001100100110111101001110101010110011011011001000010101110010011101000100101";
+
+        // Check digit computed when omitted
+        assert_eq!(modules_str(&ean13("590123412345").unwrap()), expected);
+        assert_eq!(modules_str(&ean13("5901234123457").unwrap()), expected);
+
+        // Guards are marked for extension
+        let m = ean13("5901234123457").unwrap();
+        assert_eq!(&m[..3], &[MODULE_GUARD, MODULE_SPACE, MODULE_GUARD]);
+        assert_eq!(&m[45..50], &[MODULE_SPACE, MODULE_GUARD, MODULE_SPACE, MODULE_GUARD, MODULE_SPACE]);
+
+        // Invalid inputs
+        assert!(matches!(ean13("5901234123458"), Err(Error::BarcodeCheckDigit(Symbology::Ean13))));
+        assert!(matches!(ean13("59012341234"), Err(Error::BarcodeLength(11, Symbology::Ean13))));
+        assert!(matches!(ean13("59012341234A"), Err(Error::BarcodeCharacter('A', Symbology::Ean13))));
+    }
+
+    #[test]
+    fn test_ean8() {
+        let expected = "1010001011010111101111010110111010101001110111001010001001011100101";
+
+        assert_eq!(modules_str(&ean8("9638507").unwrap()), expected);
+        assert_eq!(modules_str(&ean8("96385074").unwrap()), expected);
+
+        let m = ean8("96385074").unwrap();
+        assert_eq!(m.len(), 67);
+        assert_eq!(&m[..3], &[MODULE_GUARD, MODULE_SPACE, MODULE_GUARD]);
+        assert_eq!(&m[31..36], &[MODULE_SPACE, MODULE_GUARD, MODULE_SPACE, MODULE_GUARD, MODULE_SPACE]);
+        assert_eq!(&m[64..], &[MODULE_GUARD, MODULE_SPACE, MODULE_GUARD]);
+
+        assert!(matches!(ean8("96385075"), Err(Error::BarcodeCheckDigit(Symbology::Ean8))));
+        assert!(matches!(ean8("963850"), Err(Error::BarcodeLength(6, Symbology::Ean8))));
+    }
+
+    #[test]
+    fn test_upca() {
+        let expected = "10100011010111101010111100011010001101000110101010110110011101001100110101110010011101101100101";
+
+        assert_eq!(modules_str(&upca("03600029145").unwrap()), expected);
+        assert_eq!(modules_str(&upca("036000291452").unwrap()), expected);
+
+        let m = upca("036000291452").unwrap();
+        assert_eq!(m.len(), 95);
+        assert_eq!(&m[..3], &[MODULE_GUARD, MODULE_SPACE, MODULE_GUARD]);
+        assert_eq!(&m[45..50], &[MODULE_SPACE, MODULE_GUARD, MODULE_SPACE, MODULE_GUARD, MODULE_SPACE]);
+        assert_eq!(&m[92..], &[MODULE_GUARD, MODULE_SPACE, MODULE_GUARD]);
+
+        // UPC-A is equivalent to EAN-13 with a leading zero
+        assert_eq!(m, ean13("0036000291452").unwrap());
+
+        assert!(matches!(upca("036000291453"), Err(Error::BarcodeCheckDigit(Symbology::UpcA))));
+        assert!(matches!(upca("0360002914521"), Err(Error::BarcodeLength(13, Symbology::UpcA))));
+    }
+}
@@ -9,7 +9,12 @@ use embedded_graphics::{
     pixelcolor::BinaryColor,
 };
 
+use image::{GrayImage, Luma, DynamicImage, ImageOutputFormat};
+
+use log::debug;
+
 use crate::Error;
+use super::Rotation;
 
 /// In memory display for drawing / rendering data
 pub struct Display {
@@ -21,10 +26,7 @@ pub struct Display {
 impl Display {
     /// Create a new display with the provided height and minimum width
     pub fn new(y: usize, min_x: usize) -> Self {
-        let mut y_max = y;
-        while y_max % 8 != 0 {
-            y_max += 1;
-        }
+        let y_max = y.next_multiple_of(8);
 
         Self {
             y,
@@ -33,15 +35,32 @@ impl Display {
         }
     }
 
+    /// Create a new display with the provided height, growing in width as pixels are drawn
+    pub fn with_height(y: usize) -> Self {
+        Self::new(y, 0)
+    }
+
+    /// Create a display from row-major pixel data
+    pub fn from_image(width: usize, height: usize, pixels: &[BinaryColor]) -> Result<Self, Error> {
+        if pixels.len() != width * height {
+            return Err(Error::Render);
+        }
+
+        let mut d = Display::new(height, width);
+
+        for (i, p) in pixels.iter().enumerate() {
+            d.set(i % width, i / width, p.is_on())?;
+        }
+
+        Ok(d)
+    }
+
     /// Fetch a flipped + compressed vector image for output to printer
     pub fn image(&self) -> Result<Vec<u8>, Error> {
         // Generate new buffer
-        let mut x_len = self.data.len();
-        while x_len % 8 != 0 {
-            x_len += 1;
-        }
+        let x_len = self.data.len().next_multiple_of(8);
 
-        println!(
+        debug!(
             "Using {} rows {}({}) columns",
             self.y,
             x_len,
@@ -58,8 +77,6 @@ impl Display {
                 let v = self.get(x, y)?;
                 let p = &mut buff[i];
 
-                println!("x: {} y: {} p: {:5?} i: {} m: 0b{:08b}", x, y, v, i, m);
-
                 match v {
                     true => *p |= m,
                     false => *p &= !m,
@@ -71,24 +88,24 @@ impl Display {
     }
 
 
-    pub fn raster(&self, margins: (usize, usize, usize)) -> Result<Vec<[u8; 16]>, anyhow::Error> {
+    pub fn raster(&self, margins: (usize, usize, usize)) -> Result<Vec<[u8; 16]>, Error> {
         let s = self.size();
 
-        println!("Raster display size: {:?} output area: {:?}", s, margins);
+        debug!("Raster display size: {:?} output area: {:?}", s, margins);
         if s.height != margins.1 as u32 {
-            return Err(anyhow::anyhow!("Raster display and output size differ ({:?}, {:?})", s, margins));
+            return Err(Error::MediaSize{ render: s.height as usize, media: margins.1 });
         }
 
         let mut buff = vec![[0u8; 16]; s.width as usize];
 
-        for x in 0..(s.width as usize) {
+        for (x, b) in buff.iter_mut().enumerate() {
             for y in 0..(s.height as usize) {
                 let p = self.get(x, y)?;
 
-                let y_offset = y + margins.0 as usize;
+                let y_offset = y + margins.0;
 
                 if p {
-                    buff[x][y_offset / 8] |= 1 << 7 - (y_offset % 8);
+                    b[y_offset / 8] |= 1 << (7 - (y_offset % 8));
                 }
             }
         }
@@ -99,7 +116,7 @@ impl Display {
     /// Set a pixel value by X/Y location
     pub fn set(&mut self, x: usize, y: usize, v: bool) -> Result<(), Error> {
         // Check Y bounds
-        if y > self.y {
+        if y >= self.y {
             return Err(Error::Render);
         }
 
@@ -120,10 +137,184 @@ impl Display {
         Ok(())
     }
 
+    /// Extend the display to at least the provided width, without modifying existing pixels
+    pub fn extend(&mut self, width: usize) {
+        while width > self.data.len() {
+            self.data.push(vec![0u8; self.y_max / 8])
+        }
+    }
+
+    /// Insert blank columns at the left edge of the display, shifting existing content right
+    pub fn shift(&mut self, columns: usize) {
+        let blank = vec![vec![0u8; self.y_max / 8]; columns];
+        self.data.splice(0..0, blank);
+    }
+
+    /// Drop blank columns from the right edge of the display, down to `min_x` columns
+    pub fn trim(&mut self, min_x: usize) {
+        while self.data.len() > min_x && self.data.last().map(|c| c.iter().all(|b| *b == 0)) == Some(true) {
+            self.data.pop();
+        }
+    }
+
+    /// Invert all pixels in the display
+    pub fn invert(&mut self) {
+        for c in self.data.iter_mut() {
+            for (i, b) in c.iter_mut().enumerate() {
+                // Mask off padding bits beyond the display height
+                let bits = (self.y - (i * 8).min(self.y)).min(8);
+                *b = !*b & (0xffu16 >> (8 - bits)) as u8;
+            }
+        }
+    }
+
+    /// Set a rectangular region of pixels
+    pub fn fill(&mut self, x: usize, y: usize, w: usize, h: usize, v: bool) -> Result<(), Error> {
+        for x in x..x + w {
+            for y in y..y + h {
+                self.set(x, y, v)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Copy another display into this one with the top left corner at X/Y
+    pub fn blit(&mut self, src: &Display, x: usize, y: usize) -> Result<(), Error> {
+        let s = src.size();
+
+        for sx in 0..s.width as usize {
+            for sy in 0..s.height as usize {
+                self.set(x + sx, y + sy, src.get(sx, sy)?)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Convert the display to a greyscale image, with set pixels black on a white background
+    pub fn to_gray_image(&self) -> Result<GrayImage, Error> {
+        let s = self.size();
+        let mut i = GrayImage::from_pixel(s.width, s.height, Luma([0xff]));
+
+        for x in 0..s.width {
+            for y in 0..s.height {
+                if self.get(x as usize, y as usize)? {
+                    i.put_pixel(x, y, Luma([0x00]));
+                }
+            }
+        }
+
+        Ok(i)
+    }
+
+    /// Encode the display as an in-memory PNG
+    pub fn to_png_bytes(&self) -> Result<Vec<u8>, Error> {
+        let i = DynamicImage::ImageLuma8(self.to_gray_image()?);
+
+        let mut b = Vec::new();
+        i.write_to(&mut b, ImageOutputFormat::Png)?;
+
+        Ok(b)
+    }
+
+    /// Create a new display from the rectangular region with the top left corner at X/Y
+    pub fn crop(&self, x: usize, y: usize, w: usize, h: usize) -> Result<Display, Error> {
+        let s = self.size();
+
+        // Check region bounds
+        if x + w > s.width as usize || y + h > s.height as usize {
+            return Err(Error::Render);
+        }
+
+        let mut d = Display::new(h, w);
+
+        for cx in 0..w {
+            for cy in 0..h {
+                d.set(cx, cy, self.get(x + cx, y + cy)?)?;
+            }
+        }
+
+        Ok(d)
+    }
+
+    /// Create a new display by joining another display of the same height to the right of this one
+    pub fn concat(&self, other: &Display) -> Result<Display, Error> {
+        // Check heights match
+        if self.y != other.y {
+            return Err(Error::Render);
+        }
+
+        let mut data = self.data.clone();
+        data.extend_from_slice(&other.data);
+
+        Ok(Display{ y: self.y, y_max: self.y_max, data })
+    }
+
+    /// Create a copy of the display upscaled by an integer factor (nearest neighbour)
+    pub fn scale(&self, factor: usize) -> Result<Display, Error> {
+        let s = self.size();
+        let (w, h) = (s.width as usize, s.height as usize);
+
+        let mut d = Display::new(h * factor, w * factor);
+
+        for x in 0..w * factor {
+            for y in 0..h * factor {
+                d.set(x, y, self.get(x / factor, y / factor)?)?;
+            }
+        }
+
+        Ok(d)
+    }
+
+    /// Render the display as ASCII art, with `#` for set pixels and a newline per row
+    pub fn to_ascii(&self) -> String {
+        let s = self.size();
+        let mut a = String::with_capacity((s.width as usize + 1) * s.height as usize);
+
+        for y in 0..s.height as usize {
+            for x in 0..s.width as usize {
+                a.push(match self.get(x, y) {
+                    Ok(true) => '#',
+                    _ => ' ',
+                });
+            }
+            a.push('\n');
+        }
+
+        a
+    }
+
+    /// Create a rotated copy of the display
+    pub fn rotate(&self, rotation: Rotation) -> Result<Display, Error> {
+        let s = self.size();
+        let (w, h) = (s.width as usize, s.height as usize);
+
+        let mut d = match rotation {
+            Rotation::Rotate90 | Rotation::Rotate270 => Display::new(w, h),
+            _ => Display::new(h, w),
+        };
+
+        for x in 0..w {
+            for y in 0..h {
+                let (rx, ry) = match rotation {
+                    Rotation::None => (x, y),
+                    Rotation::Rotate90 => (h - 1 - y, x),
+                    Rotation::Rotate180 => (w - 1 - x, h - 1 - y),
+                    Rotation::Rotate270 => (y, w - 1 - x),
+                };
+
+                d.set(rx, ry, self.get(x, y)?)?;
+            }
+        }
+
+        Ok(d)
+    }
+
     /// Fetch a pixel value by X/Y location
     pub fn get(&self, x: usize, y: usize) -> Result<bool, Error> {
-        // Check Y bounds
-        if y > self.y {
+        // Check X/Y bounds
+        if x >= self.data.len() || y >= self.y {
             return Err(Error::Render);
         }
 
@@ -164,6 +355,22 @@ mod test {
 
     use super::*;
 
+    #[test]
+    fn test_new() {
+        // Height and minimum width, as used by `Render::new`
+        let d = Display::new(12, 4);
+        assert_eq!(d.size(), Size::new(4, 12));
+        assert_eq!(d.data, vec![vec![0u8; 2]; 4]);
+
+        let mut d = Display::with_height(12);
+        assert_eq!(d.size(), Size::new(0, 12));
+
+        // Displays grow in width as pixels are set, but not in height
+        d.set(5, 11, true).unwrap();
+        assert_eq!(d.size(), Size::new(6, 12));
+        assert!(d.set(0, 12, true).is_err());
+    }
+
     #[test]
     fn test_display() {
         let mut d = Display::new(8, 1);
@@ -218,7 +425,6 @@ mod test {
         );
     }
 
-    #[cfg(disabled)]
     #[test]
     fn test_raster() {
         let mut d = Display::new(112, 1);
@@ -226,14 +432,198 @@ mod test {
         d.set(1, 1, true).unwrap();
         d.set(2, 2, true).unwrap();
 
+        let mut expected = vec![[0u8; 16]; 3];
+        expected[0][1] = 0x80;
+        expected[1][1] = 0x40;
+        expected[2][1] = 0x20;
 
         assert_eq!(
-            &d.raster((8, 112, 8)).unwrap(),
-            &[
-                [0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, ],
-                [0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, ],
-                [0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, ],
-            ]
+            d.raster((8, 112, 8)).unwrap(),
+            expected,
         );
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_extend() {
+        let mut d = Display::new(8, 1);
+        d.set(0, 0, true).unwrap();
+
+        d.extend(3);
+        assert_eq!(d.size(), Size::new(3, 8));
+        assert_eq!(d.data, vec![vec![0b0000_0001], vec![0], vec![0]]);
+
+        // Never shrinks
+        d.extend(2);
+        assert_eq!(d.size(), Size::new(3, 8));
+    }
+
+    #[test]
+    fn test_rotate() {
+        let mut d = Display::new(2, 3);
+        d.set(0, 0, true).unwrap();
+
+        let tests = &[
+            (Rotation::None, Size::new(3, 2), (0, 0)),
+            (Rotation::Rotate90, Size::new(2, 3), (1, 0)),
+            (Rotation::Rotate180, Size::new(3, 2), (2, 1)),
+            (Rotation::Rotate270, Size::new(2, 3), (0, 2)),
+        ];
+
+        for (r, size, (x, y)) in tests {
+            let rotated = d.rotate(*r).unwrap();
+            assert_eq!(rotated.size(), *size, "rotation: {:?}", r);
+
+            for rx in 0..size.width as usize {
+                for ry in 0..size.height as usize {
+                    let expected = (rx, ry) == (*x, *y);
+                    assert_eq!(rotated.get(rx, ry).unwrap(), expected, "rotation: {:?} ({}, {})", r, rx, ry);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_trim() {
+        let mut d = Display::new(8, 1);
+        d.set(2, 7, true).unwrap();
+        d.extend(6);
+
+        d.trim(0);
+        assert_eq!(d.size(), Size::new(3, 8));
+
+        // Trimming is clamped to the minimum width
+        let mut d = Display::new(8, 4);
+        d.trim(4);
+        assert_eq!(d.size(), Size::new(4, 8));
+    }
+
+    #[test]
+    fn test_invert() {
+        let mut d = Display::new(12, 3);
+        d.set(0, 0, true).unwrap();
+        d.set(2, 11, true).unwrap();
+        let original = d.data.clone();
+
+        d.invert();
+        assert!(!d.get(0, 0).unwrap());
+        assert!(d.get(1, 0).unwrap());
+        assert!(!d.get(2, 11).unwrap());
+        assert_eq!(d.data[1], vec![0xff, 0x0f]);
+
+        d.invert();
+        assert_eq!(d.data, original);
+    }
+
+    #[test]
+    fn test_crop() {
+        let pattern = |x: usize, y: usize| (x * 3 + y) % 5 == 1;
+
+        let mut d = Display::new(16, 12);
+        for x in 0..12 {
+            for y in 0..16 {
+                d.set(x, y, pattern(x, y)).unwrap();
+            }
+        }
+
+        let c = d.crop(3, 5, 8, 10).unwrap();
+        assert_eq!(c.size(), Size::new(8, 10));
+
+        for x in 0..8 {
+            for y in 0..10 {
+                assert_eq!(c.get(x, y).unwrap(), pattern(x + 3, y + 5), "({}, {})", x, y);
+            }
+        }
+        assert_eq!(c.image().unwrap().len(), 10);
+
+        // Regions must be within the display
+        assert!(matches!(d.crop(8, 0, 5, 16), Err(Error::Render)));
+        assert!(matches!(d.crop(0, 1, 12, 16), Err(Error::Render)));
+    }
+
+    #[test]
+    fn test_concat() {
+        let mut a = Display::new(8, 1);
+        a.set(0, 2, true).unwrap();
+
+        let mut b = Display::new(8, 1);
+        b.set(0, 5, true).unwrap();
+
+        let c = a.concat(&b).unwrap();
+        assert_eq!(c.size(), Size::new(2, 8));
+        assert_eq!(c.data, vec![vec![0b0000_0100], vec![0b0010_0000]]);
+
+        // Heights must match
+        assert!(matches!(a.concat(&Display::new(16, 1)), Err(Error::Render)));
+    }
+
+    #[test]
+    fn test_from_image() {
+        let (on, off) = (BinaryColor::On, BinaryColor::Off);
+        #[rustfmt::skip]
+        let pixels = [
+            on,  off, off, off, off, off, off, off, off, on,
+            off, on,  off, off, off, off, off, off, on,  off,
+        ];
+
+        let d = Display::from_image(10, 2, &pixels).unwrap();
+        assert_eq!(d.size(), Size::new(10, 2));
+        assert_eq!(d.image().unwrap(), vec![
+            0b0000_0001, 0b0000_0010,
+            0b0000_0010, 0b0000_0001,
+        ]);
+
+        // Pixel count must match dimensions
+        assert!(matches!(Display::from_image(3, 2, &pixels), Err(Error::Render)));
+    }
+
+    #[test]
+    fn test_shift() {
+        let mut d = Display::new(8, 2);
+        d.set(0, 1, true).unwrap();
+
+        d.shift(3);
+        assert_eq!(d.size(), Size::new(5, 8));
+        assert_eq!(d.data, vec![vec![0], vec![0], vec![0], vec![0b0000_0010], vec![0]]);
+    }
+
+    #[test]
+    fn test_scale() {
+        let mut d = Display::new(2, 2);
+        d.set(1, 0, true).unwrap();
+
+        let s = d.scale(3).unwrap();
+        assert_eq!(s.size(), Size::new(6, 6));
+
+        for x in 0..6 {
+            for y in 0..6 {
+                assert_eq!(s.get(x, y).unwrap(), (3..6).contains(&x) && y < 3, "({}, {})", x, y);
+            }
+        }
+        assert_eq!(s.image().unwrap().len(), 6);
+    }
+
+    #[test]
+    fn test_to_ascii() {
+        let mut d = Display::new(3, 4);
+        d.set(0, 0, true).unwrap();
+        d.set(3, 0, true).unwrap();
+        d.set(1, 1, true).unwrap();
+        d.set(2, 2, true).unwrap();
+
+        assert_eq!(d.to_ascii(), "#  #\n #  \n  # \n");
+    }
+
+    #[test]
+    fn test_to_png_bytes() {
+        let mut d = Display::new(12, 5);
+        d.set(4, 7, true).unwrap();
+
+        let b = d.to_png_bytes().unwrap();
+        assert_eq!(&b[..8], b"\x89PNG\r\n\x1a\n");
+
+        let i = image::load_from_memory(&b).unwrap().into_luma8();
+        assert_eq!(i.dimensions(), (5, 12));
+        assert_eq!(i.get_pixel(4, 7), &Luma([0x00]));
+        assert_eq!(i.get_pixel(3, 7), &Luma([0xff]));
+    }
+}
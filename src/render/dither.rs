@@ -0,0 +1,65 @@
+//! Error diffusion dithering for image rendering
+// Rust PTouch Driver / Utility
+//
+// https://github.com/ryankurte/rust-ptouch
+// Copyright 2021 Ryan Kurte
+
+use image::GrayImage;
+
+use super::Dither;
+
+/// Floyd-Steinberg error distribution as (dx, dy, numerator), over 16
+const FLOYD_STEINBERG: &[(i32, i32, i32)] = &[
+    (1, 0, 7),
+    (-1, 1, 3), (0, 1, 5), (1, 1, 1),
+];
+
+/// Atkinson error distribution as (dx, dy, numerator), over 8 (only 3/4 of the error is propagated)
+const ATKINSON: &[(i32, i32, i32)] = &[
+    (1, 0, 1), (2, 0, 1),
+    (-1, 1, 1), (0, 1, 1), (1, 1, 1),
+    (0, 2, 1),
+];
+
+/// Dither a greyscale image in place, setting each pixel to 0 or 255
+pub fn dither(img: &mut GrayImage, mode: Dither) {
+    let (kernel, divisor) = match mode {
+        Dither::None => return,
+        Dither::FloydSteinberg => (FLOYD_STEINBERG, 16),
+        Dither::Atkinson => (ATKINSON, 8),
+    };
+
+    let (w, h) = (img.width() as i32, img.height() as i32);
+
+    // Working buffer with headroom for accumulated error
+    let mut buff: Vec<i32> = img.pixels().map(|p| p.0[0] as i32).collect();
+
+    for y in 0..h {
+        for x in 0..w {
+            let i = (y * w + x) as usize;
+
+            // Quantise pixel and compute error
+            let old = buff[i];
+            let new = match old < 128 {
+                true => 0,
+                false => 255,
+            };
+            let err = old - new;
+            buff[i] = new;
+
+            // Distribute error to neighbouring pixels
+            for (dx, dy, n) in kernel {
+                let (nx, ny) = (x + dx, y + dy);
+                if nx < 0 || nx >= w || ny >= h {
+                    continue;
+                }
+
+                buff[(ny * w + nx) as usize] += err * n / divisor;
+            }
+        }
+    }
+
+    for (p, v) in img.pixels_mut().zip(buff.iter()) {
+        p.0[0] = *v as u8;
+    }
+}
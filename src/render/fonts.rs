@@ -0,0 +1,85 @@
+//! Font discovery
+// Rust PTouch Driver / Utility
+//
+// https://github.com/ryankurte/rust-ptouch
+// Copyright 2021 Ryan Kurte
+
+use std::path::{Path, PathBuf};
+
+use crate::Error;
+use super::FontKind;
+
+/// Font available for rendering
+#[derive(Clone, Debug, PartialEq)]
+pub enum FontInfo {
+    /// Embedded bitmap font
+    Embedded(FontKind),
+    /// TrueType / OpenType font file discovered in a font directory,
+    /// these cannot yet be rendered (only embedded fonts are accepted by `--font`)
+    File(PathBuf),
+}
+
+impl std::fmt::Display for FontInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FontInfo::Embedded(k) => write!(f, "{} (embedded, {}x{})", k, k.char_width(), k.char_height()),
+            FontInfo::File(p) => write!(f, "{} (not yet supported)", p.display()),
+        }
+    }
+}
+
+/// List embedded fonts, followed by any `.ttf` / `.otf` files in the provided directory
+pub fn list_fonts(dir: Option<&Path>) -> Result<Vec<FontInfo>, Error> {
+    let mut fonts: Vec<_> = FontKind::ALL.iter().map(|k| FontInfo::Embedded(*k)).collect();
+
+    if let Some(d) = dir {
+        let mut files = vec![];
+        for entry in std::fs::read_dir(d)? {
+            let path = entry?.path();
+
+            let is_font = path.extension()
+                .and_then(|e| e.to_str())
+                .map(|e| e.eq_ignore_ascii_case("ttf") || e.eq_ignore_ascii_case("otf"))
+                .unwrap_or(false);
+
+            if path.is_file() && is_font {
+                files.push(path);
+            }
+        }
+
+        // Directory iteration order is not defined
+        files.sort();
+        fonts.extend(files.into_iter().map(FontInfo::File));
+    }
+
+    Ok(fonts)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_list_fonts() {
+        // Embedded fonts are always available
+        let fonts = list_fonts(None).unwrap();
+        assert!(fonts.contains(&FontInfo::Embedded(FontKind::Font12x16)));
+        assert_eq!(fonts.len(), FontKind::ALL.len());
+
+        // Font files are discovered by extension
+        let d = tempdir::TempDir::new("ptouch").unwrap();
+        for f in &["b.otf", "a.TTF", "readme.txt"] {
+            std::fs::write(d.path().join(f), b"").unwrap();
+        }
+
+        let fonts = list_fonts(Some(d.path())).unwrap();
+        assert!(fonts.contains(&FontInfo::Embedded(FontKind::Font12x16)));
+        assert_eq!(&fonts[FontKind::ALL.len()..], &[
+            FontInfo::File(d.path().join("a.TTF")),
+            FontInfo::File(d.path().join("b.otf")),
+        ]);
+
+        // Font files are marked as unsupported until TrueType rendering is available
+        assert_eq!(FontInfo::File(PathBuf::from("a.ttf")).to_string(), "a.ttf (not yet supported)");
+    }
+}
@@ -0,0 +1,288 @@
+//! Image loading and pre-processing for render operations
+// Rust PTouch Driver / Utility
+//
+// https://github.com/ryankurte/rust-ptouch
+// Copyright 2021 Ryan Kurte
+
+use std::path::Path;
+
+use image::{GrayImage, imageops};
+
+use crate::Error;
+use super::Rotation;
+
+#[cfg(feature = "json")]
+use super::Op;
+
+/// Load a list of render operations from a JSON file, or from stdin if the path is `-`
+#[cfg(feature = "json")]
+pub fn load_ops<P: AsRef<Path>>(path: P) -> Result<Vec<Op>, Error> {
+    match path.as_ref() == Path::new("-") {
+        true => read_ops(std::io::stdin().lock()),
+        false => read_ops(std::fs::File::open(path)?),
+    }
+}
+
+/// Read render operations from either a JSON list or newline-delimited JSON objects
+#[cfg(feature = "json")]
+pub fn read_ops<R: std::io::Read>(mut r: R) -> Result<Vec<Op>, Error> {
+    let mut data = String::new();
+    r.read_to_string(&mut data)?;
+
+    match data.trim_start().starts_with('[') {
+        true => Ok(serde_json::from_str(&data)?),
+        false => {
+            let ops = serde_json::Deserializer::from_str(&data).into_iter::<Op>()
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(ops)
+        },
+    }
+}
+
+/// Check whether a file is an SVG image, by extension or content
+pub fn is_svg(file: &Path, data: &[u8]) -> bool {
+    if let Some(e) = file.extension() {
+        if e.eq_ignore_ascii_case("svg") {
+            return true;
+        }
+    }
+
+    // Check for an SVG root element near the start of the document
+    let head = String::from_utf8_lossy(&data[..data.len().min(1024)]);
+    let head = head.trim_start();
+
+    head.starts_with("<svg") || (head.starts_with("<?xml") && head.contains("<svg"))
+}
+
+/// Check whether image data is a 1-bit (monochrome) BMP
+pub fn is_bilevel_bmp(data: &[u8]) -> bool {
+    data.len() > 30 && &data[..2] == b"BM" && u16::from_le_bytes([data[28], data[29]]) == 1
+}
+
+/// Greyscale image loaded for rendering
+pub struct LoadedImage {
+    /// Image luminance
+    pub image: GrayImage,
+    /// Source image is 1-bit, pixels map directly to on / off without thresholding
+    pub bilevel: bool,
+}
+
+/// Load an image file as greyscale, rasterising vector images to the provided height
+///
+/// Raster formats (PNG, BMP, etc.) are detected by magic bytes
+pub fn load_image(file: &str, height: u32) -> Result<LoadedImage, Error> {
+    let data = std::fs::read(file)?;
+
+    if is_svg(Path::new(file), &data) {
+        return Ok(LoadedImage {
+            image: load_svg(&data, height)?,
+            bilevel: false,
+        });
+    }
+
+    let img = image::load_from_memory(&data)?;
+
+    Ok(LoadedImage {
+        image: img.into_luma8(),
+        bilevel: is_bilevel_bmp(&data),
+    })
+}
+
+/// Flip and rotate an image
+pub fn transform(img: GrayImage, flip_h: bool, flip_v: bool, rotation: Rotation) -> GrayImage {
+    let mut img = img;
+
+    if flip_h {
+        imageops::flip_horizontal_in_place(&mut img);
+    }
+    if flip_v {
+        imageops::flip_vertical_in_place(&mut img);
+    }
+
+    match rotation {
+        Rotation::None => img,
+        Rotation::Rotate90 => imageops::rotate90(&img),
+        Rotation::Rotate180 => imageops::rotate180(&img),
+        Rotation::Rotate270 => imageops::rotate270(&img),
+    }
+}
+
+/// Apply brightness and contrast adjustments to image luminance
+pub fn adjust(img: &mut GrayImage, brightness: i16, contrast: f32) {
+    if brightness == 0 && contrast == 1.0 {
+        return;
+    }
+
+    for p in img.pixels_mut() {
+        let v = (p.0[0] as f32 - 128.0) * contrast + 128.0 + brightness as f32;
+        p.0[0] = v.round().clamp(0.0, 255.0) as u8;
+    }
+}
+
+/// Rasterise an SVG image to the provided height, composited onto white
+#[cfg(feature = "svg")]
+fn load_svg(data: &[u8], height: u32) -> Result<GrayImage, Error> {
+    use resvg::{usvg, tiny_skia};
+
+    let tree = usvg::Tree::from_data(data, &usvg::Options::default())?;
+    let size = tree.size();
+
+    // Scale to the target height preserving aspect ratio
+    let scale = height as f32 / size.height();
+    let width = ((size.width() * scale).round() as u32).max(1);
+
+    let mut pixmap = tiny_skia::Pixmap::new(width, height).ok_or(Error::Render)?;
+    pixmap.fill(tiny_skia::Color::WHITE);
+    resvg::render(&tree, tiny_skia::Transform::from_scale(scale, scale), &mut pixmap.as_mut());
+
+    // Convert to luminance
+    let luma = pixmap.pixels().iter().map(|p| {
+        let (r, g, b) = (p.red() as u32, p.green() as u32, p.blue() as u32);
+        ((r * 299 + g * 587 + b * 114) / 1000) as u8
+    }).collect();
+
+    GrayImage::from_raw(width, height, luma).ok_or(Error::Render)
+}
+
+#[cfg(not(feature = "svg"))]
+fn load_svg(_data: &[u8], _height: u32) -> Result<GrayImage, Error> {
+    Err(Error::ImageFormat("svg (requires the `svg` feature)".to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_is_svg() {
+        assert!(is_svg(Path::new("logo.svg"), b""));
+        assert!(is_svg(Path::new("logo.SVG"), b""));
+        assert!(is_svg(Path::new("logo"), b"  <svg xmlns=\"http://www.w3.org/2000/svg\"></svg>"));
+        assert!(is_svg(Path::new("logo"), b"<?xml version=\"1.0\"?>\n<svg></svg>"));
+
+        assert!(!is_svg(Path::new("logo.png"), b"\x89PNG\r\n"));
+        assert!(!is_svg(Path::new("logo"), b"<?xml version=\"1.0\"?>\n<html></html>"));
+    }
+
+    #[test]
+    fn test_adjust() {
+        let mut img = GrayImage::from_raw(4, 1, vec![0, 100, 160, 255]).unwrap();
+
+        // Contrast pushes values away from mid-grey
+        adjust(&mut img, 0, 2.0);
+        assert_eq!(img.into_raw(), vec![0, 72, 192, 255]);
+
+        // Brightness offsets values, clamped to range
+        let mut img = GrayImage::from_raw(4, 1, vec![0, 100, 160, 255]).unwrap();
+        adjust(&mut img, -50, 1.0);
+        assert_eq!(img.into_raw(), vec![0, 50, 110, 205]);
+    }
+
+    #[test]
+    fn test_transform() {
+        // 3x2 asymmetric pattern
+        let img = GrayImage::from_raw(3, 2, vec![1, 2, 3, 4, 5, 6]).unwrap();
+
+        let tests = &[
+            (false, false, Rotation::None, (3, 2), vec![1, 2, 3, 4, 5, 6]),
+            (true, false, Rotation::None, (3, 2), vec![3, 2, 1, 6, 5, 4]),
+            (false, true, Rotation::None, (3, 2), vec![4, 5, 6, 1, 2, 3]),
+            (false, false, Rotation::Rotate90, (2, 3), vec![4, 1, 5, 2, 6, 3]),
+            (false, false, Rotation::Rotate180, (3, 2), vec![6, 5, 4, 3, 2, 1]),
+            (true, false, Rotation::Rotate270, (2, 3), vec![1, 4, 2, 5, 3, 6]),
+        ];
+
+        for (flip_h, flip_v, r, size, expected) in tests {
+            let t = transform(img.clone(), *flip_h, *flip_v, *r);
+            assert_eq!(t.dimensions(), *size, "{} {} {:?}", flip_h, flip_v, r);
+            assert_eq!(&t.into_raw(), expected, "{} {} {:?}", flip_h, flip_v, r);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "json")]
+    fn test_load_ops() {
+        use super::super::{Render, RenderConfig, TextOptions, HAlign, VAlign};
+
+        let d = tempdir::TempDir::new("ptouch").unwrap();
+        let f = d.path().join("label.json");
+
+        std::fs::write(&f, r#"[
+            { "kind": "pad", "count": 8 },
+            { "kind": "text", "text": "hello", "h_align": "left", "bold": true },
+            { "kind": "qr", "code": "https://hello.world" }
+        ]"#).unwrap();
+
+        let ops = load_ops(&f).unwrap();
+        assert_eq!(ops, vec![
+            Op::pad(8),
+            Op::text_with_opts("hello", TextOptions{ h_align: HAlign::Left, bold: true, ..Default::default() }),
+            Op::qr("https://hello.world"),
+        ]);
+
+        let mut r = Render::new(RenderConfig::default());
+        r.render(&ops).unwrap();
+
+        // Newline-delimited operations match the list form
+        let ndjson = r#"{ "kind": "pad", "count": 8 }
+            { "kind": "text", "text": "hello", "h_align": "left", "bold": true }
+            { "kind": "qr", "code": "https://hello.world" }
+        "#;
+        assert_eq!(read_ops(ndjson.as_bytes()).unwrap(), ops);
+
+        // Capitalised alignment names from earlier templates are accepted
+        let legacy = r#"[{ "kind": "text", "text": "hello", "h_align": "Left", "v_align": "Bottom" }]"#;
+        assert_eq!(read_ops(legacy.as_bytes()).unwrap(), vec![
+            Op::text_with_opts("hello", TextOptions{ h_align: HAlign::Left, v_align: VAlign::Bottom, ..Default::default() }),
+        ]);
+
+        // Invalid documents are rejected
+        std::fs::write(&f, r#"[{ "kind": "unknown" }]"#).unwrap();
+        assert!(matches!(load_ops(&f), Err(Error::Json(_))));
+    }
+
+    #[test]
+    #[cfg(feature = "json")]
+    fn test_json_roundtrip() {
+        use super::super::*;
+
+        #[cfg_attr(not(feature = "datamatrix"), allow(unused_mut))]
+        let mut ops = vec![
+            Op::pad(4),
+            Op::text_with_opts("hello\nworld", TextOptions{
+                font: FontKind::Font6x12,
+                italic: true,
+                letter_spacing: -1,
+                rotate: Rotation::Rotate90,
+                fallback: vec![FontKind::Font6x8],
+                ..Default::default()
+            }),
+            Op::Qr{ code: "qr".to_string(), opts: QrOptions{ version: Some(3), ..Default::default() } },
+            Op::Barcode{ code: "1234".to_string(), opts: BarcodeOptions{ height: Some(20), checksum: true, ..Default::default() } },
+            Op::Image{ file: "logo.png".to_string(), opts: ImageOptions{ flip_h: true, width: Some(32), ..Default::default() } },
+            Op::runs(vec![TextRun::new("a", TextOptions::default()), TextRun::new("b", TextOptions{ bold: true, ..Default::default() })]),
+            Op::hline(8),
+            Op::HLine{ length: 8, y: Some(2), opts: LineOptions{ thickness: 2, style: LineStyle::Dashed } },
+            Op::spacer(6),
+            Op::rect(10, 12, true),
+            Op::Rect{ width: 10, height: None, filled: false, opts: LineOptions{ thickness: 3, style: LineStyle::Dotted } },
+            Op::line((0, 0), (9, 15)),
+            Op::Line{ from: (2, 4), to: (12, 4), opts: LineOptions{ thickness: 2, style: LineStyle::Dashed } },
+            Op::circle(16, false),
+            Op::Circle{ diameter: 9, filled: true, opts: LineOptions{ thickness: 2, ..Default::default() } },
+        ];
+
+        #[cfg(feature = "datamatrix")]
+        ops.push(Op::datamatrix("dm"));
+
+        // Ops survive a round trip through JSON
+        let s = to_json(&ops).unwrap();
+        let decoded = read_ops(s.as_bytes()).unwrap();
+        assert_eq!(decoded, ops);
+
+        // And the re-serialized document is structurally identical
+        let a: serde_json::Value = serde_json::from_str(&s).unwrap();
+        let b: serde_json::Value = serde_json::from_str(&to_json(&decoded).unwrap()).unwrap();
+        assert_eq!(a, b);
+    }
+}
@@ -5,18 +5,20 @@
 // Copyright 2021 Ryan Kurte
 
 use std::path::Path;
-use log::debug;
+use std::collections::HashMap;
+use log::{debug, warn};
 
 use structopt::StructOpt;
-use image::{Luma};
-use barcoders::sym::code39::Code39;
-use qrcode::QrCode;
+use image::imageops::{self, FilterType};
+use barcoders::sym::{code39::Code39, code128::Code128};
+use qrcode::{QrCode, types::{QrError, Version}};
 
 use embedded_graphics::prelude::*;
-use embedded_text::prelude::*;
 
 use embedded_graphics::{
     pixelcolor::BinaryColor,
+    primitives::{Circle, Line, Rectangle},
+    style::PrimitiveStyle,
 };
 
 #[cfg(feature = "preview")]
@@ -25,13 +27,27 @@ use embedded_graphics_simulator::{
 };
 
 use crate::Error;
+use crate::device::Media;
 
 pub mod display;
 pub use display::*;
 pub mod ops;
 pub use ops::*;
+pub mod text;
+pub use text::*;
+pub mod barcode;
+pub use barcode::*;
+pub mod pdf417;
+pub use pdf417::*;
+pub mod dither;
+pub mod loader;
+pub use loader::*;
+pub mod fonts;
+pub use fonts::*;
 
 #[derive(Clone, PartialEq, Debug, StructOpt)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct RenderConfig {
     /// Image minimum X size
     pub min_x: usize,
@@ -39,6 +55,9 @@ pub struct RenderConfig {
     pub max_x: usize,
     /// Image Y size
     pub y: usize,
+    /// Justification of content narrower than the minimum X size
+    #[structopt(long, default_value = "left")]
+    pub justify: HAlign,
 }
 
 impl Default for RenderConfig {
@@ -47,20 +66,93 @@ impl Default for RenderConfig {
             min_x: 32,
             max_x: 10 * 1024,
             y: 64,
+            justify: HAlign::Left,
         }
     }
 }
 
+impl RenderConfig {
+    /// Create a validated render configuration
+    pub fn new(min_x: usize, max_x: usize, y: usize) -> Result<Self, Error> {
+        let c = Self {
+            min_x,
+            max_x,
+            y,
+            ..Default::default()
+        };
+
+        c.validate()?;
+
+        Ok(c)
+    }
+
+    /// Check the configuration is valid, requiring `min_x <= max_x` and `y > 0`
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.min_x > self.max_x {
+            return Err(Error::InvalidConfig("minimum width exceeds maximum width"));
+        }
+
+        if self.y == 0 {
+            return Err(Error::InvalidConfig("height must be non-zero"));
+        }
+
+        Ok(())
+    }
+
+    /// Set the render height to the print area of the provided media
+    pub fn with_media(self, media: &Media) -> Self {
+        Self {
+            y: media.area().1,
+            ..self
+        }
+    }
+
+    /// Load a render configuration from a TOML file, with absent fields set to defaults
+    #[cfg(all(feature = "toml", feature = "serde"))]
+    pub fn from_toml<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::default().with_toml(path)
+    }
+
+    /// Override configuration fields with those present in a TOML file
+    #[cfg(all(feature = "toml", feature = "serde"))]
+    pub fn with_toml<P: AsRef<Path>>(self, path: P) -> Result<Self, Error> {
+        let s = std::fs::read_to_string(path)?;
+        let file: toml::Value = toml::from_str(&s)?;
+
+        // Merge file fields over the current configuration
+        let mut cfg = toml::Value::try_from(&self)?;
+        if let (Some(c), toml::Value::Table(f)) = (cfg.as_table_mut(), file) {
+            c.extend(f);
+        }
+
+        Ok(cfg.try_into()?)
+    }
+}
+
+/// Rendered label dimensions in pixels
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Dimensions {
+    /// Label length (along the tape)
+    pub width: usize,
+    /// Label height (across the tape)
+    pub height: usize,
+}
+
 pub struct Render {
     cfg: RenderConfig,
     display: Display,
 }
 
+/// Split text into lines, ignoring a single trailing newline
+fn text_lines(value: &str) -> std::str::Split<'_, char> {
+    value.strip_suffix('\n').unwrap_or(value).split('\n')
+}
+
 impl Render {
     /// Create a new render instance
     pub fn new(cfg: RenderConfig) -> Self {
         // Setup virtual display for render data
-        let display = Display::new(cfg.y as usize, cfg.min_x as usize);
+        let display = Display::new(cfg.y, cfg.min_x);
 
         // Return new renderer
         Self { cfg, display }
@@ -68,289 +160,842 @@ impl Render {
 
     /// Save the render buffer as an image
     pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), anyhow::Error> {
-        // Fetch current display size
-        let size = self.display.size();
+        // Convert display to image
+        let i = self.display.to_gray_image()?;
+
+        // Save image to file
+        i.save(path)?;
 
-        // Create image
-        let i = image::DynamicImage::new_luma8(size.width, size.height);
-        let mut i = i.into_luma8();
+        Ok(())
+    }
 
-        // Copy data into image
-        for x in 0..size.width {
-            for y in 0..size.height {
-                let p = self.display.get(x as usize, y as usize)?;
-                if !p {
-                    i.put_pixel(x, y, Luma([0xff]));
+    /// Save the render buffer as a 1-bit monochrome BMP
+    pub fn save_bmp<P: AsRef<Path>>(&self, path: P) -> Result<(), anyhow::Error> {
+        // Fetch current display size
+        let size = self.display.size();
+        let (width, height) = (size.width as usize, size.height as usize);
+
+        // Rows are packed MSB first and padded to 4-byte boundaries
+        let stride = width.div_ceil(32) * 4;
+        let offset = 14 + 40 + 2 * 4;
+        let file_len = offset + stride * height;
+
+        let mut b = Vec::with_capacity(file_len);
+
+        // File header
+        b.extend_from_slice(b"BM");
+        b.extend_from_slice(&(file_len as u32).to_le_bytes());
+        b.extend_from_slice(&[0u8; 4]);
+        b.extend_from_slice(&(offset as u32).to_le_bytes());
+
+        // Info header
+        b.extend_from_slice(&40u32.to_le_bytes());
+        b.extend_from_slice(&(width as i32).to_le_bytes());
+        b.extend_from_slice(&(height as i32).to_le_bytes());
+        b.extend_from_slice(&1u16.to_le_bytes());
+        b.extend_from_slice(&1u16.to_le_bytes());
+        b.extend_from_slice(&[0u8; 4]);
+        b.extend_from_slice(&((stride * height) as u32).to_le_bytes());
+        b.extend_from_slice(&[0u8; 16]);
+
+        // Palette, index 0 white (blank) and 1 black (printed)
+        b.extend_from_slice(&[0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00, 0x00]);
+
+        // Pixel data, stored bottom row first
+        for y in (0..height).rev() {
+            let mut row = vec![0u8; stride];
+            for x in 0..width {
+                if self.display.get(x, y)? {
+                    row[x / 8] |= 0x80 >> (x % 8);
                 }
             }
+            b.extend_from_slice(&row);
         }
 
-        // Save image to file
-        i.save(path)?;
+        std::fs::write(path, b)?;
 
         Ok(())
     }
     
 
+    /// Execute render operations, replacing `{{name}}` placeholders in text with values from `vars`.
+    ///
+    /// Unknown placeholders are left intact, or return an error when `strict` is set.
+    pub fn render_template(&mut self, ops: &[Op], vars: &HashMap<String, String>, strict: bool) -> Result<&Self, Error> {
+        let ops = ops.iter().map(|o| o.substitute(vars, strict))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        self.render(&ops)
+    }
+
     /// Execute render operations
     pub fn render(&mut self, ops: &[Op]) -> Result<&Self, Error> {
+        self.cfg.validate()?;
+
         let mut x = 0;
         for operation in ops {
             x += match operation {
                 Op::Text { text, opts } => self.render_text(x, text, opts)?,
+                Op::Runs{ runs } => self.render_runs(x, runs)?,
+                // Pad is retained as an alias of Spacer
                 Op::Pad{ count } => self.pad(x, *count)?,
-                Op::Qr{ code } => self.render_qrcode(x, code)?,
+                Op::Spacer{ width } => self.pad(x, *width)?,
+                Op::Qr{ code, opts } => self.render_qrcode(x, code, opts)?,
+                #[cfg(feature = "datamatrix")]
+                Op::DataMatrix{ code, opts } => self.render_datamatrix(x, code, opts)?,
                 Op::Barcode{ code, opts } => self.render_barcode(x, code, opts)?,
                 Op::Image{ file, opts } => self.render_image(x, file, opts)?,
+                Op::HLine{ length, y, opts } => self.render_hline(x, *length, *y, opts)?,
+                Op::Rect{ width, height, filled, opts } => self.render_rect(x, *width, *height, *filled, opts)?,
+                Op::Line{ from, to, opts } => self.render_line(x, *from, *to, opts)?,
+                Op::Circle{ diameter, filled, opts } => self.render_circle(x, *diameter, *filled, opts)?,
+            };
+
+            // Check content fits within the maximum width
+            if x > self.cfg.max_x {
+                return Err(Error::TooWide{ needed: x, max: self.cfg.max_x });
             }
         }
 
-        // TODO: store data? idk
+        // Extend the display to the full advance, including any trailing blank columns
+        self.display.extend(x);
+
+        // Justify content narrower than the minimum width
+        let offset = match self.cfg.justify {
+            HAlign::Left => 0,
+            HAlign::Centre => self.cfg.min_x.saturating_sub(x) / 2,
+            HAlign::Right => self.cfg.min_x.saturating_sub(x),
+        };
+        if offset > 0 {
+            self.display.shift(offset);
+            self.display.trim(self.cfg.min_x);
+        }
+
+        Ok(self)
+    }
+
+    /// Fetch the dimensions of the rendered label in pixels
+    pub fn dimensions(&self) -> Dimensions {
+        let s = self.display.size();
+
+        Dimensions {
+            width: s.width as usize,
+            height: s.height as usize,
+        }
+    }
+
+    /// Render operations into fixed-width columns side by side, clipping or padding each column to its width
+    pub fn columns(&mut self, columns: &[Vec<Op>], widths: &[usize]) -> Result<&Self, Error> {
+        self.cfg.validate()?;
+
+        if columns.len() != widths.len() {
+            return Err(Error::InvalidConfig("column and width counts differ"));
+        }
+
+        let total: usize = widths.iter().sum();
+        if total > self.cfg.max_x {
+            return Err(Error::TooWide{ needed: total, max: self.cfg.max_x });
+        }
+
+        // Render each column separately and copy into the label
+        let mut x = 0;
+        for (ops, w) in columns.iter().zip(widths) {
+            let d = self.render_region(ops, Some(*w), self.cfg.y)?;
+            self.display.blit(&d, x, 0)?;
+            x += w;
+        }
+
+        self.display.extend(x);
+
+        Ok(self)
+    }
+
+    /// Render operations into horizontal bands stacked from the top of the label,
+    /// with the stack vertically centred where the heights are less than the label height
+    pub fn rows(&mut self, rows: &[Vec<Op>], heights: &[usize]) -> Result<&Self, Error> {
+        self.cfg.validate()?;
+
+        if rows.len() != heights.len() {
+            return Err(Error::InvalidConfig("row and height counts differ"));
+        }
+
+        let total: usize = heights.iter().sum();
+        if total > self.cfg.y {
+            return Err(Error::InvalidConfig("row heights exceed label height"));
+        }
+
+        // Render each row separately and copy into the label
+        let mut y = (self.cfg.y - total) / 2;
+        for (ops, h) in rows.iter().zip(heights) {
+            if *h > 0 {
+                let d = self.render_region(ops, None, *h)?;
+                self.display.blit(&d, 0, y)?;
+            }
+            y += h;
+        }
 
         Ok(self)
     }
 
+    /// Render operations to a temporary display of the provided height,
+    /// padding or clipping to the width if provided
+    fn render_region(&self, ops: &[Op], width: Option<usize>, height: usize) -> Result<Display, Error> {
+        let mut r = Render::new(RenderConfig {
+            min_x: width.unwrap_or(0),
+            max_x: self.cfg.max_x.max(width.unwrap_or(0)),
+            y: height,
+            justify: HAlign::Left,
+        });
+        r.render(ops)?;
+
+        match width {
+            Some(w) => r.display.crop(0, 0, w, height),
+            None => Ok(r.display),
+        }
+    }
+
     fn render_text(&mut self, start_x: usize, value: &str, opts: &TextOptions) -> Result<usize, Error> {
-        use embedded_graphics::fonts::*;
-        use embedded_text::style::vertical_overdraw::Hidden;
+        // Resolve relative text size to a font and scale, retaining the reference glyph height
+        let reference = opts.font.char_height() * opts.scale;
+        let sized;
+        let opts = match opts.size_scale != 1.0 {
+            true => {
+                sized = opts.sized();
+                &sized
+            },
+            false => opts,
+        };
 
-        // TODO: customise styles
+        if opts.rotate != Rotation::None {
+            return self.render_text_rotated(start_x, value, opts);
+        }
 
-        // TODO: custom alignment
+        if opts.vertical {
+            return self.render_text_vertical(start_x, value, opts);
+        }
 
-        // TODO: clean this up when updated embedded-graphics font API lands 
-        // https://github.com/embedded-graphics/embedded-graphics/issues/511
+        // TODO: customise styles
 
         // Fix for escaped newlines from shell
         // Otherwise "\n" becomes "\\n" and nothing works quite right
         let value = value.replace("\\n", "\n");
 
-        // Compute maximum line width
-        let max_line_x = value
-            .split("\n")
-            .map(|line| opts.font.char_width() * line.len() + 1)
-            .max()
-            .unwrap();
-        let max_x = self.cfg.max_x.min(start_x + max_line_x);
-
-        // Create textbox instance
-        let tb = TextBox::new(
-            &value,
-            Rectangle::new(
-                Point::new(start_x as i32, 0 as i32),
-                Point::new(max_x as i32, self.cfg.y as i32),
-            ),
-        );
+        // Parse inline markup on each source line, so markers are not measured and spans may wrap
+        let mut styled: Vec<Vec<StyledChar>> = text_lines(&value)
+            .map(|l| match opts.markup {
+                true => parse_markup(l),
+                false => plain(l),
+            })
+            .collect();
+
+        // Scale text to fit the label height
+        let fitted;
+        let opts = match opts.fit {
+            true => {
+                fitted = TextOptions {
+                    scale: opts.fit_scale(styled.len(), self.cfg.y),
+                    ..opts.clone()
+                };
+                &fitted
+            },
+            false => opts,
+        };
+
+        // Wrap lines to the remaining label (or maximum text) width
+        let mut width = self.cfg.max_x.saturating_sub(start_x);
+        if let Some(w) = opts.max_width {
+            width = width.min(w);
+        }
+        if opts.wrap {
+            styled = styled.iter().flat_map(|l| wrap_styled(l, width, opts)).collect();
+        }
+
+        // Limit the number of lines, marking the last visible line where text is removed
+        if let Some(max_lines) = opts.max_lines {
+            if styled.len() > max_lines {
+                styled.truncate(max_lines);
+                if let Some(last) = styled.last_mut() {
+                    *last = ellipsize_styled(last, width, opts);
+                }
+            }
+        }
+
+        // Truncate lines exceeding the width
+        if opts.max_width.is_some() || opts.ellipsis {
+            styled = styled.iter().map(|l| truncate_styled(l, width, opts.ellipsis, opts)).collect();
+        }
+
+        let lines: Vec<String> = styled.iter().map(|l| unstyled(l)).collect();
+
+        // Empty text renders nothing
+        if lines.iter().all(|l| l.is_empty()) {
+            return Ok(0);
+        }
+
+        // Compute line widths (allowing for styled overhang) and available width
+        let line_widths: Vec<usize> = styled.iter().map(|l| styled_width(l, opts)).collect();
+        let max_x = line_widths.iter().copied().max().unwrap_or(0);
+
+        // Check text fits before drawing
+        if start_x + max_x > self.cfg.max_x {
+            return Err(Error::TooWide{ needed: start_x + max_x, max: self.cfg.max_x });
+        }
+
+        // Compute text height and vertical offset
+        let v_height = opts.font.char_height() * opts.scale + opts.line_spacing;
+        let t_height = opts.text_height(lines.len());
 
-        debug!("Textbox: {:?}", tb);
+        // Text taller than the label is clamped to the top edge
+        if t_height > self.cfg.y {
+            warn!("Text height ({}px) exceeds label height ({}px), output will be clipped", t_height, self.cfg.y);
+        }
+
+        // Resized text shares the baseline of text at the reference size
+        let drop = reference.saturating_sub(opts.font.char_height() * opts.scale);
+        let block = t_height + drop;
 
-        #[cfg(nope)]
-        let a = match opts.h_align {
-            HAlign::Centre => CenterAligned,
-            HAlign::Left => LeftAligned,
-            HAlign::Right => RightAligned,
-            HAlign::Justify => Justified,
+        let base_y = drop + match opts.v_align {
+            VAlign::Top => 0,
+            VAlign::Centre => (self.cfg.y / 2).saturating_sub(block / 2),
+            VAlign::Bottom => self.cfg.y.saturating_sub(block),
         };
-        #[cfg(nope)]
-        let v = match opts.v_align {
-            VAlign::Centre => CenterAligned,
-            VAlign::Top => TopAligned,
-            VAlign::Bottom => BottomAligned,
+
+        // Apply baseline shift, keeping text within the label
+        let shift = (opts.baseline_shift * reference as f32).round() as isize;
+        let base_y = match shift >= 0 {
+            true => base_y.saturating_sub(shift as usize),
+            false => (base_y + shift.unsigned_abs()).min(self.cfg.y.saturating_sub(t_height).max(base_y)),
         };
 
-        let a = CenterAligned;
-        let v = CenterAligned;
-        let h = Exact(Hidden);
-        let l = 4;
+        debug!("Text: {:?} (width: {} height: {})", lines, max_x, t_height);
+
+        // Fill background for inverted text
+        if opts.invert {
+            self.display.fill(start_x, 0, max_x, self.cfg.y, true)?;
+        }
 
-        // Render with loaded style
-        let res = match opts.font {
-            FontKind::Font6x6 => {
-                let ts = TextBoxStyleBuilder::new(Font6x6)
-                    .text_color(BinaryColor::On)
-                    .height_mode(h)
-                    .alignment(a)
-                    .line_spacing(l)
-                    .vertical_alignment(v)
-                    .build();
+        // Render each line with independent horizontal alignment
+        for i in 0..lines.len() {
+            let slack = max_x.saturating_sub(line_widths[i]);
 
-                let tb = tb.into_styled(ts);
+            let h_align = opts.line_align.get(i).unwrap_or(&opts.h_align);
+            let x = start_x + match h_align {
+                HAlign::Left => 0,
+                HAlign::Centre => slack / 2,
+                HAlign::Right => slack,
+            };
+            let y = base_y + i * v_height;
 
-                tb.draw(&mut self.display).unwrap();
+            // Reverse character order for right to left text
+            let chars: Vec<&StyledChar> = match opts.rtl {
+                true => styled[i].iter().rev().collect(),
+                false => styled[i].iter().collect(),
+            };
 
-                tb.size()
-            }
-            FontKind::Font6x8 => {
-                let ts = TextBoxStyleBuilder::new(Font6x8)
-                    .text_color(BinaryColor::On)
-                    .height_mode(h)
-                    .alignment(a)
-                    .line_spacing(l)
-                    .vertical_alignment(v)
-                    .build();
+            for (j, s) in chars.iter().enumerate() {
+                let c = &s.c;
 
-                let tb = tb.into_styled(ts);
+                // Apply inline styles
+                let char_opts = opts.styled(s.bold, s.italic);
+                let mut d = GlyphTarget::new(&mut self.display, Point::new(x as i32, y as i32), &char_opts);
 
-                tb.draw(&mut self.display).unwrap();
+                // Select the first font containing a glyph for the character
+                let font = match opts.glyph_font(*c) {
+                    Some(f) => f,
+                    None => {
+                        warn!("No glyph for character '{}' in font {} (fallback: {:?})", c, opts.font, opts.fallback);
+                        opts.font
+                    }
+                };
+
+                // Fallback glyphs are aligned to the bottom of the line
+                let dy = opts.font.char_height().saturating_sub(font.char_height());
+                let p = Point::new((j * opts.pitch()) as i32, dy as i32);
 
-                tb.size()
+                let mut b = [0u8; 4];
+                font.draw(&mut d, c.encode_utf8(&mut b), p)?;
             }
-            FontKind::Font6x12 => {
-                let ts = TextBoxStyleBuilder::new(Font6x12)
-                    .text_color(BinaryColor::On)
-                    .height_mode(h)
-                    .alignment(a)
-                    .line_spacing(l)
-                    .vertical_alignment(v)
-                    .build();
 
-                let tb = tb.into_styled(ts);
+            // Draw decorations across the line
+            let t = opts.decoration_thickness();
+            let h = opts.font.char_height() * opts.scale;
 
-                tb.draw(&mut self.display).unwrap();
+            if opts.underline {
+                self.display.fill(x, y + h - t, line_widths[i], t, !opts.invert)?;
+            }
 
-                tb.size()
+            if opts.strikethrough {
+                self.display.fill(x, y + h / 2 - t / 2, line_widths[i], t, !opts.invert)?;
             }
-            FontKind::Font8x16 => {
-                let ts = TextBoxStyleBuilder::new(Font8x16)
-                    .text_color(BinaryColor::On)
-                    .height_mode(h)
-                    .alignment(a)
-                    .line_spacing(l)
-                    .vertical_alignment(v)
-                    .build();
+        }
 
-                let tb = tb.into_styled(ts);
+        Ok(max_x)
+    }
 
-                tb.draw(&mut self.display).unwrap();
+    fn render_runs(&mut self, start_x: usize, runs: &[TextRun]) -> Result<usize, Error> {
+        // Runs are bottom aligned within a band of the tallest glyph height
+        let height = runs.iter()
+            .map(|r| {
+                let o = r.opts.sized();
+                o.font.char_height() * o.scale
+            })
+            .max()
+            .unwrap_or(0)
+            .min(self.cfg.y);
 
-                tb.size()
-            }
-            FontKind::Font12x16 => {
-                let ts = TextBoxStyleBuilder::new(Font12x16)
-                    .text_color(BinaryColor::On)
-                    .height_mode(h)
-                    .alignment(a)
-                    .line_spacing(l)
-                    .vertical_alignment(v)
-                    .build();
+        if height == 0 {
+            return Ok(0);
+        }
 
-                let tb = tb.into_styled(ts);
+        // Band is positioned using the alignment of the first run
+        let slack = self.cfg.y - height;
+        let y = match runs[0].opts.v_align {
+            VAlign::Top => 0,
+            VAlign::Centre => slack / 2,
+            VAlign::Bottom => slack,
+        };
+
+        let mut x = start_x;
+        for run in runs {
+            let opts = TextOptions {
+                v_align: VAlign::Bottom,
+                h_align: HAlign::Left,
+                ..run.opts.clone()
+            };
 
-                tb.draw(&mut self.display).unwrap();
+            let d = self.render_region(&[Op::Text{ text: run.text.clone(), opts }], None, height)?;
+            self.display.blit(&d, x, y)?;
 
-                tb.size()
-            }
-            FontKind::Font24x32 => {
-                let ts = TextBoxStyleBuilder::new(Font24x32)
-                    .text_color(BinaryColor::On)
-                    .height_mode(h)
-                    .alignment(a)
-                    .line_spacing(l)
-                    .vertical_alignment(v)
-                    .build();
+            x += d.size().width as usize;
+        }
 
-                let tb = tb.into_styled(ts);
+        Ok(x - start_x)
+    }
 
-                tb.draw(&mut self.display).unwrap();
+    fn render_text_rotated(&mut self, start_x: usize, value: &str, opts: &TextOptions) -> Result<usize, Error> {
+        let inner = TextOptions {
+            rotate: Rotation::None,
+            ..opts.clone()
+        };
 
-                tb.size()
-            }
+        // Setup a temporary render, such that the rotated text fits the label height
+        let cfg = match opts.rotate {
+            Rotation::Rotate90 | Rotation::Rotate270 => {
+                let lines = text_lines(&value.replace("\\n", "\n")).count();
+                RenderConfig {
+                    min_x: 0,
+                    max_x: self.cfg.y,
+                    y: inner.text_height(lines),
+                    justify: HAlign::Left,
+                }
+            },
+            _ => RenderConfig {
+                min_x: 0,
+                ..self.cfg.clone()
+            },
+        };
+
+        let mut r = Render::new(cfg);
+        let w = r.render_text(0, value, &inner)?;
+        if w == 0 {
+            return Ok(0);
+        }
+
+        // Extend the temporary display to the full text width
+        r.display.extend(w);
+
+        // Rotate and copy into the label
+        let d = r.display.rotate(opts.rotate)?;
+        let s = d.size();
+
+        let slack = self.cfg.y.saturating_sub(s.height as usize);
+        let y = match opts.v_align {
+            VAlign::Top => 0,
+            VAlign::Centre => slack / 2,
+            VAlign::Bottom => slack,
+        };
+
+        self.display.blit(&d, start_x, y)?;
+
+        Ok(s.width as usize)
+    }
+
+    fn render_text_vertical(&mut self, start_x: usize, value: &str, opts: &TextOptions) -> Result<usize, Error> {
+        // Each character is centred in its own row, note that as all
+        // fonts are monospaced (and lack wide glyphs) columns are uniform
+        let inner = TextOptions {
+            vertical: false,
+            wrap: false,
+            h_align: HAlign::Centre,
+            ..opts.clone()
         };
 
-        Ok(res.width as usize)
+        // Render lines as columns from left to right
+        let value = value.replace("\\n", "\n");
+        let mut x = start_x;
+
+        for (i, line) in text_lines(&value).enumerate() {
+            if i > 0 {
+                x += opts.line_spacing;
+            }
+
+            let column: Vec<String> = line.chars().map(|c| c.to_string()).collect();
+            x += self.render_text(x, &column.join("\n"), &inner)?;
+        }
+
+        Ok(x - start_x)
     }
 
     fn pad(&mut self, x: usize, columns: usize) -> Result<usize, Error> {
-        self.display
-            .draw_pixel(Pixel(Point::new((x + columns) as i32, 0), BinaryColor::Off))?;
+        // Reserve blank columns, existing columns are already blank as content is drawn left to right
+        self.display.extend(x + columns);
         Ok(columns)
     }
 
-    fn render_qrcode(&mut self, x_start: usize, value: &str) -> Result<usize, Error> {
-        // Generate QR
-        let qr = QrCode::new(value).unwrap();
-        let img = qr.render()
-            .dark_color(image::Rgb([0, 0, 0]))
-            .light_color(image::Rgb([255, 255, 255]))
-            .quiet_zone(false)
-            .max_dimensions(self.cfg.y as u32, self.cfg.y as u32)
-            .build();
+    fn render_qrcode(&mut self, x_start: usize, value: &str, opts: &QrOptions) -> Result<usize, Error> {
+        if let Some(v) = opts.version {
+            if !(1..=40).contains(&v) {
+                return Err(Error::InvalidConfig("QR version must be between 1 and 40"));
+            }
+        }
+
+        // Generate QR, using a fixed version if specified
+        let qr = match opts.version {
+            Some(v) => QrCode::with_version(value, Version::Normal(v as i16), opts.ec_level.into())
+                .map_err(|e| match e {
+                    QrError::DataTooLong => Error::QrVersion(v),
+                    _ => Error::Qr(e),
+                })?,
+            None => QrCode::with_error_correction_level(value, opts.ec_level.into())?,
+        };
 
-        // Generate offsets
-        let y_offset = (self.cfg.y as i32 - img.height() as i32) / 2;
-        let x_offset = x_start as i32 + y_offset;
+        // Render module matrix
+        let dark: Vec<_> = qr.to_colors().iter().map(|c| *c == qrcode::Color::Dark).collect();
+        let scale = opts.module_size(qr.width(), self.cfg.y);
 
-        // Write to display
-        for (x, y, v) in img.enumerate_pixels() {
-            let c = match v {
-                image::Rgb([0, 0, 0]) => BinaryColor::On,
-                _ => BinaryColor::Off,
-            };
-            let p = Pixel(Point::new(x_offset + x as i32, y_offset + y as i32), c);
-            self.display.draw_pixel(p)?
+        self.render_matrix(x_start, &dark, qr.width(), opts.quiet_zone, scale)
+    }
+
+    #[cfg(feature = "datamatrix")]
+    fn render_datamatrix(&mut self, x_start: usize, value: &str, opts: &DataMatrixOptions) -> Result<usize, Error> {
+        use datamatrix::{DataMatrix, SymbolList};
+
+        // Generate Data Matrix, restricted to square symbols
+        let dm = DataMatrix::encode_str(value, SymbolList::default().enforce_square())?;
+        let bitmap = dm.bitmap();
+
+        // Render module matrix
+        let scale = opts.module_size(bitmap.width(), self.cfg.y);
+
+        self.render_matrix(x_start, bitmap.bits(), bitmap.width(), opts.quiet_zone, scale)
+    }
+
+    /// Render a square 2D module matrix vertically centred on the label, returning the advance
+    fn render_matrix(&mut self, x_start: usize, dark: &[bool], width: usize, quiet_zone: usize, scale: usize) -> Result<usize, Error> {
+        // Check matrix (including quiet zone) fits the label height
+        let modules = width + 2 * quiet_zone;
+        if modules > self.cfg.y {
+            debug!("Matrix ({} modules) exceeds label height ({})", modules, self.cfg.y);
+            return Err(Error::Render);
+        }
+
+        let size = modules * scale;
+        let y_offset = (self.cfg.y - size) / 2;
+
+        // Write dark modules to display
+        for (i, _) in dark.iter().enumerate().filter(|(_, d)| **d) {
+            let x = x_start + (i % width + quiet_zone) * scale;
+            let y = y_offset + (i / width + quiet_zone) * scale;
+
+            self.display.fill(x, y, scale, scale, true)?;
         }
 
-        Ok(img.width() as usize + x_offset as usize)
+        // Extend display to include the trailing quiet zone
+        self.display.extend(x_start + size);
+
+        Ok(size)
     }
 
     fn render_barcode(&mut self, x_start: usize, value: &str, opts: &BarcodeOptions) -> Result<usize, Error> {
-        let barcode = Code39::new(value).unwrap();
-        let encoded: Vec<u8> = barcode.encode();
+        let w = opts.module_width();
+        if w == 0 {
+            return Err(Error::InvalidConfig("barcode module width must be non-zero"));
+        }
 
-        let x_offset = x_start as i32;
+        // Check characters are supported
+        if let Some(c) = value.chars().find(|c| !opts.symbology.is_valid(*c)) {
+            return Err(Error::BarcodeCharacter(c, opts.symbology));
+        }
 
-        // TODO: something is not quite right here...
-        for i in 0..encoded.len() {
-            //let v = (encoded[i / 8] & ( 1 << (i % 8) ) ) == 0;
+        // Compute bar height and offset
+        let max_height = self.cfg.y.saturating_sub(2 * opts.y_offset);
+        let height = opts.height.unwrap_or(max_height).min(max_height);
+        let y_offset = (self.cfg.y - height) / 2;
 
-            for y in opts.y_offset..self.cfg.y-opts.y_offset {
-                let c = match encoded[i] != 0 {
-                    true => BinaryColor::On,
-                    false => BinaryColor::Off,
-                };
+        // Leave room for text beneath the data bars (always reserved for retail symbologies)
+        let text_height = BARCODE_FONT.char_height() + 1;
+        let reserved = opts.human_readable || opts.symbology.is_retail();
+        if reserved && height <= text_height {
+            return Err(Error::BarcodeHeight(height, opts.symbology, text_height + 1));
+        }
+
+        let bar_height = match reserved {
+            true => height - text_height,
+            false => height,
+        };
+
+        // Stacked symbologies require a minimum number of rows
+        let row_min = PDF417_ROW_HEIGHT * w;
+        if opts.symbology == Symbology::Pdf417 && bar_height < PDF417_MIN_ROWS * row_min {
+            return Err(Error::BarcodeHeight(bar_height, opts.symbology, PDF417_MIN_ROWS * row_min));
+        }
+
+        // Encode barcode to rows of modules
+        let rows: Vec<Vec<u8>> = match opts.symbology {
+            Symbology::Code39 if opts.checksum => vec![Code39::with_checksum(value)?.encode()],
+            Symbology::Code39 => vec![Code39::new(value)?.encode()],
+            // Prefix selects code set B
+            Symbology::Code128 => vec![Code128::new(format!("\u{0181}{}", value))?.encode()],
+            Symbology::Ean13 => vec![ean13(value)?],
+            Symbology::Ean8 => vec![ean8(value)?],
+            Symbology::UpcA => vec![upca(value)?],
+            Symbology::Pdf417 => pdf417(value, bar_height / row_min)?,
+        };
+        let encoded = &rows[0];
+
+        // Offset bars by the leading quiet zone
+        let quiet = opts.quiet_zone.unwrap_or_else(|| opts.symbology.quiet_zone()) * w;
+        let x_start = x_start + quiet;
+
+        // Split bar height between rows, centring the stack
+        let row_height = bar_height / rows.len();
+        let y_start = y_offset + (bar_height - row_height * rows.len()) / 2;
 
-                let p = Pixel(Point::new(x_offset + i as i32, y as i32), c);
-                self.display.draw_pixel(p)?
+        // Write bars to display, guard bars extend to the full height
+        for (r, row) in rows.iter().enumerate() {
+            let y = y_start + r * row_height;
+
+            for (i, e) in row.iter().enumerate() {
+                match *e {
+                    MODULE_BAR => self.display.fill(x_start + i * w, y, w, row_height, true)?,
+                    MODULE_GUARD => self.display.fill(x_start + i * w, y, w, height, true)?,
+                    _ => (),
+                }
+            }
+        }
+
+        // Write human readable text centred beneath the bars
+        if opts.human_readable {
+            let y = y_offset + height - BARCODE_FONT.char_height();
+
+            for (t, m) in human_readable(value, opts.symbology, encoded.len())? {
+                let t_width = t.chars().count() * BARCODE_FONT.char_width();
+                let centre = x_start + (m.start + m.end) * w / 2;
+                let x = centre.saturating_sub(t_width / 2).max(x_start);
+
+                // Clear any guard bars behind the text
+                self.display.fill(x, y - 1, t_width, text_height, false)?;
+                BARCODE_FONT.draw(&mut self.display, &t, Point::new(x as i32, y as i32))?;
             }
         }
 
-        Ok(encoded.len() + x_offset as usize)
+        // Extend display to the end of the trailing quiet zone
+        let len = encoded.len() * w;
+        self.display.extend(x_start + len + quiet);
+
+        Ok(len + 2 * quiet)
     }
 
-    fn render_image(&mut self, x_start: usize, file: &str, _opts: &ImageOptions) -> Result<usize, Error> {
-        // Load image and convert to greyscale
-        let img = image::io::Reader::open(file)?.decode()?;
-        let i = img.clone().into_luma8();
-        let d = i.dimensions();
+    fn render_hline(&mut self, x_start: usize, length: usize, y: Option<usize>, opts: &LineOptions) -> Result<usize, Error> {
+        // Centre vertically unless a row is specified
+        let y = y.unwrap_or_else(|| self.cfg.y.saturating_sub(opts.thickness) / 2);
 
-        // TODO: Rescale based on image options
+        // Clip thickness to the label height
+        let h = opts.thickness.min(self.cfg.y.saturating_sub(y));
 
-        let x_offset = x_start as i32;
-        let y_offset = (self.cfg.y / 2) as i32 - (d.1 as usize / 2) as i32;
+        self.display.extend(x_start + length);
+        for i in (0..length).filter(|i| opts.style.is_set(*i, opts.thickness)) {
+            self.display.fill(x_start + i, y, 1, h, true)?;
+        }
+
+        Ok(length)
+    }
 
-        // Copy image data into display
-        for x in 0..d.0 as i32 {
-            for y in 0..d.1 as i32 {
-                let p = i.get_pixel(x as u32, y as u32);
+    fn render_rect(&mut self, x_start: usize, width: usize, height: Option<usize>, filled: bool, opts: &LineOptions) -> Result<usize, Error> {
+        // Default to the label height, centring smaller rectangles
+        let h = height.unwrap_or(self.cfg.y).min(self.cfg.y);
+        let y = (self.cfg.y - h) / 2;
 
-                let c = match p.0[0] == 0 {
-                    true => BinaryColor::On,
-                    false => BinaryColor::Off,
-                };
+        self.display.extend(x_start + width);
+        if width == 0 || h == 0 {
+            return Ok(width);
+        }
+
+        let style = match filled {
+            true => PrimitiveStyle::with_fill(BinaryColor::On),
+            false => PrimitiveStyle::with_stroke(BinaryColor::On, opts.thickness as u32),
+        };
+
+        let top_left = Point::new(x_start as i32, y as i32);
+        let bottom_right = top_left + Point::new(width as i32 - 1, h as i32 - 1);
+        Rectangle::new(top_left, bottom_right)
+            .into_styled(style)
+            .draw(&mut self.display)?;
 
-                let p = Pixel(Point::new(x_offset + x as i32, y_offset + y as i32), c);
-                self.display.draw_pixel(p)?
+        Ok(width)
+    }
+
+    fn render_line(&mut self, x_start: usize, from: (usize, usize), to: (usize, usize), opts: &LineOptions) -> Result<usize, Error> {
+        let width = from.0.max(to.0) + 1;
+        self.display.extend(x_start + width);
+
+        let p = |(x, y): (usize, usize)| Point::new((x_start + x) as i32, y as i32);
+
+        let line = Line::new(p(from), p(to))
+            .into_styled(PrimitiveStyle::with_stroke(BinaryColor::On, opts.thickness as u32));
+
+        // Apply line style by distance along the major axis
+        let start = p(from);
+        let pixels = line.into_iter().filter(|Pixel(q, _)| {
+            let d = (q.x - start.x).abs().max((q.y - start.y).abs());
+            opts.style.is_set(d as usize, opts.thickness)
+        });
+
+        // Thick lines may extend beyond the label
+        self.draw_clipped(pixels)?;
+
+        Ok(width)
+    }
+
+    fn render_circle(&mut self, x_start: usize, diameter: usize, filled: bool, opts: &LineOptions) -> Result<usize, Error> {
+        self.display.extend(x_start + diameter);
+        if diameter == 0 {
+            return Ok(0);
+        }
+
+        // Circles span `2 * radius + 1` pixels, so even diameters are reduced by one
+        let r = (diameter - 1) / 2;
+        let centre = Point::new((x_start + r) as i32, (self.cfg.y / 2) as i32);
+
+        let style = match filled {
+            true => PrimitiveStyle::with_fill(BinaryColor::On),
+            false => PrimitiveStyle::with_stroke(BinaryColor::On, opts.thickness as u32),
+        };
+
+        // Circles larger than the label height are clipped
+        self.draw_clipped(&Circle::new(centre, r as u32).into_styled(style))?;
+
+        Ok(diameter)
+    }
+
+    /// Draw pixels, discarding those outside the label
+    fn draw_clipped<I: IntoIterator<Item = Pixel<BinaryColor>>>(&mut self, pixels: I) -> Result<(), Error> {
+        for Pixel(p, c) in pixels {
+            if p.x >= 0 && p.y >= 0 && (p.y as usize) < self.cfg.y {
+                self.display.set(p.x as usize, p.y as usize, c.is_on())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn render_image(&mut self, x_start: usize, file: &str, opts: &ImageOptions) -> Result<usize, Error> {
+        // Target height defaults to (and is limited by) the label height
+        let height = opts.height.unwrap_or(self.cfg.y).min(self.cfg.y).max(1) as u32;
+
+        // Load image as greyscale
+        let LoadedImage{ image: i, bilevel } = load_image(file, height)?;
+
+        // Apply flips and rotation
+        let i = transform(i, opts.flip_h, opts.flip_v, opts.rotate);
+        let (w, h) = i.dimensions();
+        if w == 0 || h == 0 {
+            return Ok(0);
+        }
+
+        // Compute target width, defaulting to preserve aspect ratio
+        let fit_width = ((w as u64 * height as u64 + h as u64 / 2) / h as u64).max(1) as u32;
+        let width = opts.width.map(|v| v.max(1) as u32).unwrap_or(fit_width);
+
+        // Avoid blur when enlarging or scaling 1-bit images
+        let filter = match height > h || bilevel {
+            true => FilterType::Nearest,
+            false => FilterType::Triangle,
+        };
+
+        let mut i = match opts.scale_mode {
+            ScaleMode::Fit => imageops::resize(&i, fit_width, height, filter),
+            ScaleMode::Stretch => imageops::resize(&i, width, height, filter),
+            ScaleMode::Fill => {
+                // Scale to cover the target area then crop overflow evenly
+                let s = (width as f64 / w as f64).max(height as f64 / h as f64);
+                let sw = ((w as f64 * s).round() as u32).max(width);
+                let sh = ((h as f64 * s).round() as u32).max(height);
+
+                let r = imageops::resize(&i, sw, sh, filter);
+                imageops::crop_imm(&r, (sw - width) / 2, (sh - height) / 2, width, height).to_image()
+            },
+        };
+        let (width, height) = i.dimensions();
+        let y_offset = (self.cfg.y - height as usize) / 2;
+
+        // Adjust levels of greyscale sources
+        if !bilevel {
+            adjust(&mut i, opts.brightness, opts.contrast);
+        }
+
+        // Apply dithering, producing pixels that are fully on or off
+        let threshold = match opts.dither {
+            _ if bilevel => IMAGE_THRESHOLD,
+            Dither::None => opts.threshold,
+            _ => {
+                dither::dither(&mut i, opts.dither);
+                IMAGE_THRESHOLD
+            },
+        };
+
+        // Copy thresholded image data into display
+        for (x, y, p) in i.enumerate_pixels() {
+            if p.0[0] < threshold {
+                self.display.set(x_start + x as usize, y_offset + y as usize, true)?;
             }
         }
 
-        Ok(d.0 as usize + x_offset as usize)
+        // Extend display to the end of the image
+        self.display.extend(x_start + width as usize);
+
+        Ok(width as usize)
+    }
+
+    /// Rotate the rendered label
+    pub fn rotate(&mut self, rotation: Rotation) -> Result<&Self, Error> {
+        self.display = self.display.rotate(rotation)?;
+
+        Ok(self)
     }
 
     /// Raster data to a ptouch compatible buffer for printing
-    pub fn raster(&self, margins: (usize, usize, usize)) -> Result<Vec<[u8; 16]>, anyhow::Error> {
+    pub fn raster(&self, margins: (usize, usize, usize)) -> Result<Vec<[u8; 16]>, Error> {
         self.display.raster(margins)
     }
 
+    /// Trim blank columns from the end of the render, down to the configured `min_x`
+    pub fn trim(&mut self) -> &Self {
+        self.display.trim(self.cfg.min_x);
+        self
+    }
+
+    /// Fetch the packed monochrome raster for the rendered display
+    ///
+    /// Output is row-major, with each of the `height` rows packed into
+    /// `ceil(width / 8)` bytes and the leftmost pixel of each byte in the
+    /// least significant bit (as per [`Display::image`]). Set bits are printed
+    /// dots. The width includes any padding to the configured `min_x`, with
+    /// rows padded to a whole byte with blank pixels.
+    pub fn bytes(&self) -> Result<Vec<u8>, Error> {
+        self.display.image()
+    }
+
     /// Show the rendered image (note that this blocks until the window is closed)
     #[cfg(feature = "preview")]
     pub fn show(&self) -> Result<(), anyhow::Error> {
@@ -381,3 +1026,1731 @@ impl Render {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::Luma;
+
+    /// Fetch the horizontal extent of set pixels within a range of rows
+    fn x_extent(r: &Render, rows: std::ops::Range<usize>) -> Option<(usize, usize)> {
+        let mut e: Option<(usize, usize)> = None;
+
+        for x in 0..r.display.size().width as usize {
+            for y in rows.clone() {
+                if r.display.get(x, y).unwrap() {
+                    e = Some(match e {
+                        Some((min, _)) => (min, x),
+                        None => (x, x),
+                    });
+                }
+            }
+        }
+
+        e
+    }
+
+    /// Count set pixels
+    fn count(r: &Render) -> usize {
+        let s = r.display.size();
+        let mut n = 0;
+
+        for x in 0..s.width as usize {
+            for y in 0..s.height as usize {
+                if r.display.get(x, y).unwrap() {
+                    n += 1;
+                }
+            }
+        }
+
+        n
+    }
+
+    /// Fetch the vertical extent of set pixels
+    fn y_extent(r: &Render) -> Option<(usize, usize)> {
+        y_extent_cols(r, 0..r.display.size().width as usize)
+    }
+
+    /// Fetch the vertical extent of set pixels within a range of columns
+    fn y_extent_cols(r: &Render, cols: std::ops::Range<usize>) -> Option<(usize, usize)> {
+        let mut e: Option<(usize, usize)> = None;
+
+        for y in 0..r.display.size().height as usize {
+            for x in cols.clone() {
+                if r.display.get(x, y).unwrap() {
+                    e = Some(match e {
+                        Some((min, _)) => (min, y),
+                        None => (y, y),
+                    });
+                }
+            }
+        }
+
+        e
+    }
+
+    #[test]
+    fn test_text_align() {
+        let extents = |h_align| {
+            let mut r = Render::new(RenderConfig::default());
+            let opts = TextOptions {
+                font: FontKind::Font6x8,
+                h_align,
+                ..Default::default()
+            };
+            r.render(&[Op::text_with_opts("I\nIII", opts)]).unwrap();
+
+            // Text block is vertically centred, splitting lines around y / 2
+            (x_extent(&r, 0..32).unwrap(), x_extent(&r, 32..64).unwrap())
+        };
+
+        let (l0, l1) = extents(HAlign::Left);
+        let (c0, c1) = extents(HAlign::Centre);
+        let (r0, r1) = extents(HAlign::Right);
+
+        // The longest line fills the text area for all alignments
+        assert_eq!(l1, c1);
+        assert_eq!(l1, r1);
+
+        // Shorter lines are offset by half / all of the remaining width
+        let slack = 2 * FontKind::Font6x8.char_width();
+        assert_eq!(c0.0, l0.0 + slack / 2);
+        assert_eq!(r0.0, l0.0 + slack);
+    }
+
+    #[test]
+    fn test_text_scale() {
+        let extents = |scale| {
+            let mut r = Render::new(RenderConfig::default());
+            let opts = TextOptions {
+                font: FontKind::Font6x8,
+                scale,
+                ..Default::default()
+            };
+            r.render(&[Op::text_with_opts("H", opts)]).unwrap();
+
+            let (x0, x1) = x_extent(&r, 0..64).unwrap();
+            let (y0, y1) = y_extent(&r).unwrap();
+            (x1 - x0 + 1, y1 - y0 + 1)
+        };
+
+        let (w1, h1) = extents(1);
+        let (w2, h2) = extents(2);
+
+        assert_eq!(w2, w1 * 2);
+        assert_eq!(h2, h1 * 2);
+    }
+
+    #[test]
+    fn test_text_line_spacing() {
+        let extents = |line_spacing| {
+            let mut r = Render::new(RenderConfig::default());
+            let opts = TextOptions {
+                font: FontKind::Font6x8,
+                line_spacing,
+                ..Default::default()
+            };
+            r.render(&[Op::text_with_opts("H\nH", opts)]).unwrap();
+
+            y_extent(&r).unwrap()
+        };
+
+        let (a0, a1) = extents(0);
+        let (b0, b1) = extents(10);
+
+        // Block height grows by the additional spacing
+        assert_eq!(b1 - b0, a1 - a0 + 10);
+
+        // And remains vertically centred
+        assert_eq!(a0 - b0, 5);
+        assert_eq!(b1 - a1, 5);
+    }
+
+    #[test]
+    fn test_text_bold() {
+        let render = |bold| {
+            let mut r = Render::new(RenderConfig::default());
+            let opts = TextOptions {
+                bold,
+                ..Default::default()
+            };
+            r.render(&[Op::text_with_opts("Hello", opts)]).unwrap();
+            r
+        };
+
+        let normal = render(false);
+        let bold = render(true);
+
+        assert!(count(&bold) > count(&normal));
+    }
+
+    #[test]
+    fn test_text_italic() {
+        let mut r = Render::new(RenderConfig::default());
+        let opts = TextOptions {
+            font: FontKind::Font6x8,
+            italic: true,
+            ..Default::default()
+        };
+        let w = r.render_text(0, "IIII", &opts).unwrap();
+
+        // Top rows are sheared right of the bottom rows
+        let top = x_extent(&r, 0..30).unwrap();
+        let bottom = x_extent(&r, 34..64).unwrap();
+        assert!(top.1 > bottom.1);
+
+        // Returned width covers the overhang
+        assert!(top.1 < w);
+    }
+
+    #[test]
+    fn test_text_underline() {
+        let mut r = Render::new(RenderConfig::default());
+        let opts = TextOptions {
+            h_align: HAlign::Left,
+            underline: true,
+            ..Default::default()
+        };
+        let w = r.render_text(0, "Hello", &opts).unwrap();
+
+        // Bottom row of the (vertically centred) text is fully set
+        let h = opts.font.char_height();
+        let y = 64 / 2 - h / 2 + h - 1;
+        for x in 0..w {
+            assert!(r.display.get(x, y).unwrap());
+        }
+        assert_eq!(r.display.size().width as usize, w);
+    }
+
+    #[test]
+    fn test_text_strikethrough() {
+        let mut r = Render::new(RenderConfig::default());
+        let opts = TextOptions {
+            h_align: HAlign::Left,
+            underline: true,
+            strikethrough: true,
+            ..Default::default()
+        };
+        let w = r.render_text(4, "Hello", &opts).unwrap();
+
+        // Middle and bottom rows of the text span the glyph extents
+        let h = opts.font.char_height();
+        let top = 64 / 2 - h / 2;
+        for y in &[top + h / 2, top + h - 1] {
+            assert_eq!(x_extent(&r, *y..*y + 1), Some((4, 4 + w - 1)));
+            for x in 4..4 + w {
+                assert!(r.display.get(x, *y).unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn test_text_letter_spacing() {
+        let width = |letter_spacing| {
+            let mut r = Render::new(RenderConfig::default());
+            let opts = TextOptions {
+                font: FontKind::Font6x8,
+                letter_spacing,
+                ..Default::default()
+            };
+            let w = r.render_text(0, "HHHH", &opts).unwrap();
+
+            let (x0, x1) = x_extent(&r, 0..64).unwrap();
+            (w, x1 - x0)
+        };
+
+        let (w0, e0) = width(0);
+        let (w1, e1) = width(2);
+
+        // Spacing is added between each of the characters
+        assert_eq!(w1, w0 + 3 * 2);
+        assert_eq!(e1, e0 + 3 * 2);
+
+        // Negative spacing is limited to half the character width
+        assert_eq!(width(-100).0, width(-3).0);
+    }
+
+    #[test]
+    fn test_text_wrap() {
+        let opts = TextOptions {
+            font: FontKind::Font6x8,
+            wrap: true,
+            ..Default::default()
+        };
+
+        // Lines are split on whitespace to fit, with long words broken
+        assert_eq!(
+            wrap("the quick brown fox", 60, &opts),
+            vec!["the quick", "brown fox"]
+        );
+        assert_eq!(
+            wrap("a abcdefghijklmn", 60, &opts),
+            vec!["a", "abcdefghij", "klmn"]
+        );
+
+        // Rendered text is limited to the maximum width
+        let mut r = Render::new(RenderConfig {
+            max_x: 100,
+            ..Default::default()
+        });
+        let w = r.render_text(0, "the quick brown\nfox jumps over the lazy dog", &opts).unwrap();
+        assert!(w <= 100);
+        assert!(r.display.size().width <= 100);
+    }
+
+    #[test]
+    fn test_text_fit() {
+        let opts = TextOptions {
+            font: FontKind::Font6x8,
+            fit: true,
+            ..Default::default()
+        };
+
+        for (text, lines) in &[("H", 1), ("H\nH", 2), ("H\nH\nH", 3)] {
+            let mut r = Render::new(RenderConfig::default());
+            r.render_text(0, text, &opts).unwrap();
+
+            // Chosen scale is the largest at which the text fits
+            let scale = opts.fit_scale(*lines, 64);
+            let fitted = TextOptions { scale, ..opts.clone() };
+            assert!(fitted.text_height(*lines) <= 64);
+
+            let larger = TextOptions { scale: scale + 1, ..opts.clone() };
+            assert!(larger.text_height(*lines) > 64);
+
+            // And is applied when rendering
+            let mut f = Render::new(RenderConfig::default());
+            f.render_text(0, text, &TextOptions { fit: false, ..fitted }).unwrap();
+            assert_eq!(y_extent(&r), y_extent(&f));
+            assert!(y_extent(&r).unwrap().1 < 64);
+        }
+    }
+
+    #[test]
+    fn test_text_rotate() {
+        let opts = TextOptions {
+            font: FontKind::Font6x8,
+            ..Default::default()
+        };
+
+        let mut r = Render::new(RenderConfig::default());
+        let w = r.render_text(0, "HELLO", &opts).unwrap();
+
+        for rotate in &[Rotation::Rotate90, Rotation::Rotate270] {
+            let opts = TextOptions { rotate: *rotate, ..opts.clone() };
+            let mut rotated = Render::new(RenderConfig::default());
+            let a = rotated.render_text(4, "HELLO", &opts).unwrap();
+
+            // Advance is the rotated block footprint
+            assert_eq!(a, 8);
+
+            let (x0, x1) = x_extent(&rotated, 0..64).unwrap();
+            assert!(x0 >= 4 && x1 < 4 + a);
+
+            let (y0, y1) = y_extent(&rotated).unwrap();
+            assert!(y1 - y0 < w && y1 - y0 > w - 8);
+        }
+
+        let opts = TextOptions { rotate: Rotation::Rotate180, ..opts };
+        let mut rotated = Render::new(RenderConfig::default());
+        assert_eq!(rotated.render_text(0, "HELLO", &opts).unwrap(), w);
+    }
+
+    #[test]
+    fn test_text_vertical() {
+        let opts = TextOptions {
+            font: FontKind::Font6x8,
+            vertical: true,
+            ..Default::default()
+        };
+
+        let mut r = Render::new(RenderConfig::default());
+        let w = r.render_text(0, "II\nIII", &opts).unwrap();
+
+        // Two columns separated by line spacing
+        assert_eq!(w, 6 + 4 + 6);
+
+        // Characters are stacked, with the longer column taller
+        let (a0, a1) = y_extent_cols(&r, 0..6).unwrap();
+        let (b0, b1) = y_extent_cols(&r, 10..16).unwrap();
+        assert!(b0 < a0 && b1 > a1);
+        assert!(b1 - b0 > 2 * 8);
+    }
+
+    #[test]
+    fn test_text_rtl() {
+        let opts = TextOptions {
+            font: FontKind::Font6x8,
+            h_align: HAlign::Left,
+            ..Default::default()
+        };
+        let rtl = TextOptions { rtl: true, ..opts.clone() };
+
+        // First logical character is placed at the right edge
+        let mut r = Render::new(RenderConfig::default());
+        r.render_text(0, "H  ", &rtl).unwrap();
+        let (x0, x1) = x_extent(&r, 0..64).unwrap();
+        assert!(x0 >= 12 && x1 < 18);
+
+        // Matching reversed left to right text
+        let mut l = Render::new(RenderConfig::default());
+        l.render_text(0, "  H", &opts).unwrap();
+        assert_eq!(x_extent(&l, 0..64), Some((x0, x1)));
+    }
+
+    #[test]
+    fn test_text_fallback() {
+        assert!(FontKind::Font6x12.has_glyph('e'));
+        assert!(!FontKind::Font6x12.has_glyph('é'));
+        assert!(FontKind::Font6x8.has_glyph('é'));
+
+        let render = |fallback, text| {
+            let mut r = Render::new(RenderConfig::default());
+            let opts = TextOptions {
+                font: FontKind::Font6x12,
+                fallback,
+                ..Default::default()
+            };
+            r.render_text(0, text, &opts).unwrap();
+            count(&r)
+        };
+
+        // Missing glyphs are drawn as '?' without a fallback
+        assert_eq!(render(vec![], "é"), render(vec![], "?"));
+
+        // And using the first font containing the glyph with fallbacks
+        let n = render(vec![FontKind::Font6x12, FontKind::Font6x8], "é");
+        assert_ne!(n, render(vec![], "?"));
+
+        let mut r = Render::new(RenderConfig::default());
+        r.render_text(0, "é", &TextOptions { font: FontKind::Font6x8, ..Default::default() }).unwrap();
+        assert_eq!(n, count(&r));
+    }
+
+    #[test]
+    fn test_text_invert() {
+        let render = |invert| {
+            let mut r = Render::new(RenderConfig::default());
+            let opts = TextOptions {
+                invert,
+                ..Default::default()
+            };
+            let w = r.render(&[Op::pad(8), Op::text_with_opts("Hi", opts), Op::pad(8)]).unwrap().display.size().width;
+            (r, w)
+        };
+
+        let (normal, w0) = render(false);
+        let (inverted, w1) = render(true);
+        assert_eq!(w0, w1);
+
+        // Text block is filled with glyphs unset
+        let (x0, x1) = x_extent(&inverted, 0..64).unwrap();
+        let text = count(&normal);
+        assert_eq!(count(&inverted), (x1 - x0 + 1) * 64 - text);
+
+        // Without affecting surrounding ops
+        assert!(x0 >= 8);
+    }
+
+    #[test]
+    fn test_qrcode() {
+        let opts = QrOptions::default();
+        let qr = QrCode::new("hello").unwrap();
+        let width = qr.width();
+
+        let mut r = Render::new(RenderConfig::default());
+        let w = r.render(&[Op::pad(4), Op::qr("hello")]).unwrap().display.size().width as usize - 4;
+
+        // Modules are scaled to fit the label height
+        let scale = 64 / (width + 2 * opts.quiet_zone);
+        assert_eq!(w, (width + 2 * opts.quiet_zone) * scale);
+
+        // Quiet zone is blank
+        assert_eq!(x_extent(&r, 0..64), Some((4 + opts.quiet_zone * scale, 4 + (opts.quiet_zone + width) * scale - 1)));
+
+        // Sample module centres and compare with the code
+        let y_offset = (64 - w) / 2;
+        for (i, c) in qr.to_colors().iter().enumerate() {
+            let x = 4 + (i % width + opts.quiet_zone) * scale + scale / 2;
+            let y = y_offset + (i / width + opts.quiet_zone) * scale + scale / 2;
+
+            assert_eq!(r.display.get(x, y).unwrap(), *c == qrcode::Color::Dark);
+        }
+    }
+
+    #[test]
+    fn test_qrcode_ec_level() {
+        let value = "https://hello.world";
+
+        let modules: Vec<_> = [EcLevel::L, EcLevel::H].iter().map(|l| {
+            let opts = QrOptions{ quiet_zone: 0, ec_level: *l, version: None };
+            let width = QrCode::with_error_correction_level(value, (*l).into()).unwrap().width();
+
+            // Rendered width should match the selected level
+            let mut r = Render::new(RenderConfig{ y: 128, ..Default::default() });
+            assert_eq!(r.render_qrcode(0, value, &opts).unwrap(), width * (128 / width));
+
+            width
+        }).collect();
+
+        // Higher error correction requires a larger module matrix
+        assert!(modules[1] > modules[0], "modules: {:?}", modules);
+    }
+
+    #[test]
+    fn test_qrcode_version() {
+        // Pinned versions are used regardless of data length
+        for value in &["a", "hello world"] {
+            let opts = QrOptions{ quiet_zone: 0, version: Some(3), ..Default::default() };
+
+            let mut r = Render::new(RenderConfig{ y: 116, ..Default::default() });
+            assert_eq!(r.render_qrcode(0, value, &opts).unwrap(), 29 * 4);
+        }
+
+        // Data exceeding the pinned version is an error
+        let opts = QrOptions{ version: Some(1), ..Default::default() };
+        let mut r = Render::new(RenderConfig::default());
+        assert!(matches!(r.render_qrcode(0, "https://hello.world/a/much/longer/value", &opts), Err(Error::QrVersion(1))));
+
+        // Versions outside 1..=40 are rejected rather than wrapped
+        for v in &[0, 41, 65537] {
+            let opts = QrOptions{ version: Some(*v), ..Default::default() };
+            let mut r = Render::new(RenderConfig::default());
+            assert!(matches!(r.render_qrcode(0, "a", &opts), Err(Error::InvalidConfig(_))));
+        }
+    }
+
+    #[test]
+    fn test_barcode_code128() {
+        let opts = BarcodeOptions{ symbology: Symbology::Code128, module_width: 2, quiet_zone: Some(0), ..Default::default() };
+
+        let mut r = Render::new(RenderConfig::default());
+        let w = r.render_barcode(0, "PTouch", &opts).unwrap();
+
+        // Read back modules from the centre row
+        let modules: String = (0..w).step_by(2)
+            .map(|x| match r.display.get(x, 32).unwrap() {
+                true => '1',
+                false => '0',
+            }).collect();
+
+        // Start B, 6 characters, checksum, stop and termination bar
+        assert_eq!(modules.len(), 11 * 8 + 13);
+        assert!(modules.starts_with("11010010000"), "start: {}", modules);
+        assert!(modules.ends_with("1100011101011"), "stop: {}", modules);
+
+        // Checksum (104 + sum(position * value)) % 103 = 55
+        assert_eq!(&modules[77..88], "11101000110");
+
+        // Bars are inset by the Y offset
+        assert_eq!(y_extent(&r), Some((4, 59)));
+    }
+
+    #[test]
+    fn test_barcode_code39() {
+        let opts = BarcodeOptions{ quiet_zone: Some(0), ..Default::default() };
+
+        let mut r = Render::new(RenderConfig::default());
+        let w = r.render_barcode(0, "CODE39", &opts).unwrap();
+
+        // Start, 6 characters, stop (12 modules + gap each, without trailing gap)
+        assert_eq!(w, 13 * 8 - 1);
+
+        // Module width scales the barcode
+        let opts = BarcodeOptions{ module_width: 3, quiet_zone: Some(0), ..Default::default() };
+        let mut r = Render::new(RenderConfig::default());
+        assert_eq!(r.render_barcode(0, "CODE39", &opts).unwrap(), w * 3);
+
+        // Mod-43 check digit, (12 + 24 + 13 + 14 + 3 + 9) % 43 = 32 ('W')
+        let opts = BarcodeOptions{ checksum: true, quiet_zone: Some(0), ..Default::default() };
+        let mut a = Render::new(RenderConfig::default());
+        let mut b = Render::new(RenderConfig::default());
+        assert_eq!(a.render_barcode(0, "CODE39", &opts).unwrap(), w + 13);
+        b.render_barcode(0, "CODE39W", &BarcodeOptions{ quiet_zone: Some(0), ..Default::default() }).unwrap();
+        assert_eq!(a.display.image().unwrap(), b.display.image().unwrap());
+
+        // Characters outside the alphabet are rejected
+        let mut r = Render::new(RenderConfig::default());
+        assert!(matches!(r.render_barcode(0, "code39", &BarcodeOptions::default()), Err(Error::BarcodeCharacter('c', Symbology::Code39))));
+    }
+
+    #[test]
+    fn test_barcode_ean13() {
+        let opts = BarcodeOptions{ symbology: Symbology::Ean13, quiet_zone: Some(0), ..Default::default() };
+
+        let mut r = Render::new(RenderConfig::default());
+        assert_eq!(r.render_barcode(0, "590123412345", &opts).unwrap(), 95);
+
+        // Guard bars extend to the full height, data bars leave room for digits
+        assert_eq!(y_extent_cols(&r, 0..1), Some((4, 59)));
+        assert_eq!(y_extent_cols(&r, 3..45), Some((4, 59 - 9)));
+    }
+
+    #[test]
+    fn test_barcode_human_readable() {
+        let opts = BarcodeOptions{ human_readable: true, module_width: 2, quiet_zone: Some(0), ..Default::default() };
+
+        let mut r = Render::new(RenderConfig::default());
+        let w = r.render_barcode(0, "AB", &opts).unwrap();
+
+        // Bars shrink to leave room for text
+        let text_rows = 59 - 7..60;
+        assert_eq!(y_extent_cols(&r, 0..2), Some((4, 59 - 9)));
+
+        // Text is centred within the barcode
+        let (min, max) = x_extent(&r, text_rows).unwrap();
+        assert!(min > 0 && max < w);
+        assert!((min as i32 - (w - 1 - max) as i32).abs() <= 2, "text extent: {} {} (width {})", min, max, w);
+
+        // Retail digits sit either side of the centre guard
+        let opts = BarcodeOptions{ symbology: Symbology::Ean8, human_readable: true, quiet_zone: Some(0), ..Default::default() };
+        let mut r = Render::new(RenderConfig::default());
+        r.render_barcode(0, "9638507", &opts).unwrap();
+
+        let set = |cols: std::ops::Range<usize>| cols.flat_map(|x| (59 - 7..60).map(move |y| (x, y)))
+            .filter(|(x, y)| r.display.get(*x, *y).unwrap())
+            .count();
+        assert!(set(3..31) > 0 && set(36..64) > 0);
+    }
+
+    #[test]
+    fn test_barcode_double() {
+        let render = |opts: &BarcodeOptions| {
+            let mut r = Render::new(RenderConfig::default());
+            let w = r.render_barcode(0, "AB", opts).unwrap();
+            (w, r.bytes().unwrap())
+        };
+
+        // Deprecated double option maps to a module width of 2
+        let double = BarcodeOptions{ double: true, ..Default::default() };
+        assert_eq!(double.module_width(), 2);
+        assert_eq!(render(&double), render(&BarcodeOptions{ module_width: 2, ..Default::default() }));
+    }
+
+    #[test]
+    fn test_barcode_module_width() {
+        for symbology in &[Symbology::Code39, Symbology::Code128, Symbology::Ean8, Symbology::Pdf417] {
+            let opts = BarcodeOptions{ symbology: *symbology, module_width: 0, ..Default::default() };
+            let mut r = Render::new(RenderConfig::default());
+            assert!(matches!(r.render_barcode(0, "1234567", &opts), Err(Error::InvalidConfig(_))));
+        }
+    }
+
+    #[test]
+    fn test_barcode_text_height() {
+        // Labels without room for text beneath the bars are rejected
+        for y in &[4, 8, 9] {
+            let cfg = RenderConfig{ y: *y, ..Default::default() };
+
+            let opts = BarcodeOptions{ human_readable: true, y_offset: 0, ..Default::default() };
+            let mut r = Render::new(cfg.clone());
+            assert!(matches!(r.render_barcode(0, "AB", &opts), Err(Error::BarcodeHeight(h, Symbology::Code39, 10)) if h == *y));
+
+            let opts = BarcodeOptions{ symbology: Symbology::Ean8, y_offset: 0, ..Default::default() };
+            let mut r = Render::new(cfg);
+            assert!(matches!(r.render_barcode(0, "9638507", &opts), Err(Error::BarcodeHeight(_, Symbology::Ean8, 10))));
+        }
+
+        // Bars alone fit
+        let opts = BarcodeOptions{ y_offset: 0, ..Default::default() };
+        let mut r = Render::new(RenderConfig{ y: 8, ..Default::default() });
+        r.render_barcode(0, "AB", &opts).unwrap();
+    }
+
+    #[test]
+    fn test_barcode_quiet_zone() {
+        let tests = &[
+            (BarcodeOptions{ symbology: Symbology::Code128, ..Default::default() }, 10),
+            (BarcodeOptions{ symbology: Symbology::Code128, module_width: 2, ..Default::default() }, 20),
+            (BarcodeOptions{ symbology: Symbology::Ean8, ..Default::default() }, 7),
+            (BarcodeOptions{ quiet_zone: Some(4), ..Default::default() }, 4),
+        ];
+
+        for (opts, quiet) in tests {
+            let mut r = Render::new(RenderConfig::default());
+            let w = r.render_barcode(0, "1234567", opts).unwrap();
+
+            // Advance includes margins, which are left blank
+            assert_eq!(r.display.size().width as usize, w);
+            assert_eq!(x_extent(&r, 0..64), Some((*quiet, w - quiet - 1)), "opts: {:?}", opts);
+        }
+    }
+
+    #[cfg(feature = "datamatrix")]
+    #[test]
+    fn test_datamatrix() {
+        use datamatrix::{DataMatrix, SymbolList};
+
+        let value = "PTouch";
+        let dm = DataMatrix::encode_str(value, SymbolList::default().enforce_square()).unwrap().bitmap();
+        let width = dm.width();
+
+        let opts = DataMatrixOptions::default();
+        let scale = 64 / (width + 2 * opts.quiet_zone);
+
+        let mut r = Render::new(RenderConfig::default());
+        let w = r.render(&[Op::pad(4), Op::datamatrix(value)]).unwrap().display.size().width as usize - 4;
+
+        // Square symbol scaled to the label height with a blank quiet zone
+        assert_eq!(w, (width + 2 * opts.quiet_zone) * scale);
+        assert_eq!(x_extent(&r, 0..64), Some((4 + opts.quiet_zone * scale, 4 + (opts.quiet_zone + width) * scale - 1)));
+
+        // Sample module centres and compare with the symbol
+        let y_offset = (64 - w) / 2;
+        for (i, d) in dm.bits().iter().enumerate() {
+            let x = 4 + (i % width + opts.quiet_zone) * scale + scale / 2;
+            let y = y_offset + (i / width + opts.quiet_zone) * scale + scale / 2;
+
+            assert_eq!(r.display.get(x, y).unwrap(), *d);
+        }
+    }
+
+    #[test]
+    fn test_barcode_pdf417() {
+        let opts = BarcodeOptions{ symbology: Symbology::Pdf417, module_width: 2, quiet_zone: Some(0), ..Default::default() };
+
+        let mut r = Render::new(RenderConfig::default());
+        let w = r.render_barcode(0, "PTouch", &opts).unwrap();
+        let rows = pdf417("PTouch", 56 / 6).unwrap();
+
+        // Rows scaled to module width and height
+        assert_eq!(w, rows[0].len() * 2);
+        let row_height = 56 / rows.len();
+        assert!(row_height >= 6);
+
+        // Start and stop patterns present on each row
+        for i in 0..rows.len() {
+            let y = 4 + (56 - row_height * rows.len()) / 2 + i * row_height + row_height / 2;
+            let modules: String = (0..w).step_by(2)
+                .map(|x| match r.display.get(x, y).unwrap() {
+                    true => '1',
+                    false => '0',
+                }).collect();
+
+            assert!(modules.starts_with("11111111010101000"), "row {}: {}", i, modules);
+            assert!(modules.ends_with("111111101000101001"), "row {}: {}", i, modules);
+        }
+
+        // Minimum height is enforced
+        let mut r = Render::new(RenderConfig{ y: 24, ..Default::default() });
+        assert!(matches!(r.render_barcode(0, "PTouch", &opts), Err(Error::BarcodeHeight(16, Symbology::Pdf417, 18))));
+    }
+
+    #[test]
+    fn test_image() {
+        let dir = tempdir::TempDir::new("ptouch").unwrap();
+        let file = dir.path().join("checker.png");
+
+        // 4x4 checkerboard with 1px cells
+        let img = image::GrayImage::from_fn(4, 4, |x, y| match (x + y) % 2 {
+            0 => Luma([0u8]),
+            _ => Luma([255u8]),
+        });
+        img.save(&file).unwrap();
+
+        // Scaled to label height, preserving aspect ratio
+        let mut r = Render::new(RenderConfig{ y: 8, ..Default::default() });
+        let w = r.render_image(0, file.to_str().unwrap(), &ImageOptions::default()).unwrap();
+        assert_eq!(w, 8);
+
+        for x in 0..8 {
+            for y in 0..8 {
+                assert_eq!(r.display.get(x, y).unwrap(), (x / 2 + y / 2) % 2 == 0, "({}, {})", x, y);
+            }
+        }
+
+        // Load failures are reported as errors
+        let mut r = Render::new(RenderConfig::default());
+        assert!(matches!(r.render_image(0, "missing.png", &ImageOptions::default()), Err(Error::Io(_))));
+
+        let file = dir.path().join("invalid.png");
+        std::fs::write(&file, b"not an image").unwrap();
+        assert!(matches!(r.render_image(0, file.to_str().unwrap(), &ImageOptions::default()), Err(Error::Image(_))));
+    }
+
+    #[test]
+    fn test_image_dither() {
+        let dir = tempdir::TempDir::new("ptouch").unwrap();
+        let file = dir.path().join("gradient.png");
+
+        // Horizontal gradient from 100 to 200
+        let img = image::GrayImage::from_fn(100, 64, |x, _y| Luma([100 + x as u8]));
+        img.save(&file).unwrap();
+
+        let mut counts = vec![];
+        for d in &[Dither::None, Dither::FloydSteinberg, Dither::Atkinson] {
+            let opts = ImageOptions{ dither: *d, ..Default::default() };
+
+            let mut r = Render::new(RenderConfig::default());
+            r.render_image(0, file.to_str().unwrap(), &opts).unwrap();
+            counts.push(count(&r));
+        }
+
+        // Thresholding sets only pixels below 128 (28%)
+        assert_eq!(counts[0], 28 * 64);
+
+        // Error diffusion approximates the mean darkness (~41%)
+        let expected: i32 = 6400 * (255 - 150) / 255;
+        assert!((counts[1] as i32 - expected).abs() < 100, "counts: {:?}", counts);
+        assert!(counts[2] > counts[0], "counts: {:?}", counts);
+    }
+
+    #[test]
+    fn test_image_threshold() {
+        let dir = tempdir::TempDir::new("ptouch").unwrap();
+        let file = dir.path().join("grey.png");
+
+        // Mid-grey with lighter and darker bands
+        let img = image::GrayImage::from_fn(64, 64, |x, _y| Luma([96 + (x / 16) as u8 * 32]));
+        img.save(&file).unwrap();
+
+        let render = |opts: &ImageOptions| {
+            let mut r = Render::new(RenderConfig::default());
+            r.render_image(0, file.to_str().unwrap(), opts).unwrap();
+            64 * 64 - count(&r)
+        };
+
+        // Raising the threshold sets more pixels, reducing off-pixels
+        let off: Vec<_> = [64, 128, 192].iter()
+            .map(|t| render(&ImageOptions{ threshold: *t, ..Default::default() }))
+            .collect();
+        assert_eq!(off, vec![64 * 64, 48 * 64, 16 * 64]);
+
+        // Threshold is ignored when dithering
+        let a = render(&ImageOptions{ dither: Dither::FloydSteinberg, threshold: 64, ..Default::default() });
+        let b = render(&ImageOptions{ dither: Dither::FloydSteinberg, threshold: 192, ..Default::default() });
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn test_image_scale_mode() {
+        let dir = tempdir::TempDir::new("ptouch").unwrap();
+        let file = dir.path().join("wide.png");
+
+        // 2:1 image, left half set
+        let img = image::GrayImage::from_fn(32, 16, |x, _y| match x < 16 {
+            true => Luma([0u8]),
+            false => Luma([255u8]),
+        });
+        img.save(&file).unwrap();
+
+        let tests = &[
+            // Fit to label height, preserving aspect ratio
+            (ScaleMode::Fit, Some(40), None, 128, (0, 63)),
+            // Fill target width, cropping overflow
+            (ScaleMode::Fill, Some(64), None, 64, (0, 63)),
+            (ScaleMode::Fill, Some(200), None, 200, (0, 63)),
+            // Stretch to exact size
+            (ScaleMode::Stretch, Some(40), Some(32), 40, (16, 47)),
+        ];
+
+        for (mode, width, height, w, y) in tests {
+            let opts = ImageOptions{ scale_mode: *mode, width: *width, height: *height, ..Default::default() };
+
+            let mut r = Render::new(RenderConfig::default());
+            assert_eq!(r.render_image(0, file.to_str().unwrap(), &opts).unwrap(), *w, "mode: {:?}", mode);
+            assert_eq!(r.display.size().width as usize, *w);
+            assert_eq!(y_extent(&r), Some(*y), "mode: {:?}", mode);
+        }
+
+        // Fill crops evenly, showing the centre of the image
+        let opts = ImageOptions{ scale_mode: ScaleMode::Fill, width: Some(64), ..Default::default() };
+        let mut r = Render::new(RenderConfig::default());
+        r.render_image(0, file.to_str().unwrap(), &opts).unwrap();
+        assert_eq!(x_extent(&r, 0..64), Some((0, 31)));
+    }
+
+    #[cfg(feature = "svg")]
+    #[test]
+    fn test_image_svg() {
+        let dir = tempdir::TempDir::new("ptouch").unwrap();
+        let file = dir.path().join("logo");
+
+        // 2:1 vector image with the left half filled, detected by content
+        let svg = r#"<svg xmlns="http://www.w3.org/2000/svg" width="20" height="10"><rect x="0" y="0" width="10" height="10" fill="black"/></svg>"#;
+        std::fs::write(&file, svg).unwrap();
+
+        // Rasterised at label height
+        let mut r = Render::new(RenderConfig::default());
+        assert_eq!(r.render_image(0, file.to_str().unwrap(), &ImageOptions::default()).unwrap(), 128);
+        assert_eq!(x_extent(&r, 0..64), Some((0, 63)));
+        assert_eq!(y_extent(&r), Some((0, 63)));
+    }
+
+    /// Encode a minimal bottom-up 1-bit BMP with a black / white palette
+    fn bmp_1bit(w: u32, h: u32, f: impl Fn(u32, u32) -> bool) -> Vec<u8> {
+        let stride = (w as usize).div_ceil(32) * 4;
+        let size = 62 + stride * h as usize;
+
+        let mut b = vec![];
+        b.extend_from_slice(b"BM");
+        b.extend_from_slice(&(size as u32).to_le_bytes());
+        b.extend_from_slice(&[0u8; 4]);
+        b.extend_from_slice(&62u32.to_le_bytes());
+
+        b.extend_from_slice(&40u32.to_le_bytes());
+        b.extend_from_slice(&(w as i32).to_le_bytes());
+        b.extend_from_slice(&(h as i32).to_le_bytes());
+        b.extend_from_slice(&1u16.to_le_bytes());
+        b.extend_from_slice(&1u16.to_le_bytes());
+        b.extend_from_slice(&[0u8; 24]);
+
+        // Palette, index 0 black and 1 white
+        b.extend_from_slice(&[0, 0, 0, 0, 255, 255, 255, 0]);
+
+        for y in (0..h).rev() {
+            let mut row = vec![0u8; stride];
+            for x in 0..w {
+                if !f(x, y) {
+                    row[x as usize / 8] |= 0x80 >> (x % 8);
+                }
+            }
+            b.extend_from_slice(&row);
+        }
+
+        b
+    }
+
+    #[test]
+    fn test_image_bmp() {
+        let dir = tempdir::TempDir::new("ptouch").unwrap();
+        let pattern = |x: u32, y: u32| (x == y) || x == 5;
+
+        // Monochrome BMP, detected by magic bytes
+        let file = dir.path().join("mono.dat");
+        std::fs::write(&file, bmp_1bit(6, 4, pattern)).unwrap();
+
+        // Pixels map directly, ignoring threshold and dithering
+        let opts = ImageOptions{ threshold: 0, dither: Dither::Atkinson, ..Default::default() };
+
+        let mut r = Render::new(RenderConfig{ y: 4, ..Default::default() });
+        assert_eq!(r.render_image(0, file.to_str().unwrap(), &opts).unwrap(), 6);
+
+        for x in 0..6 {
+            for y in 0..4 {
+                assert_eq!(r.display.get(x, y).unwrap(), pattern(x as u32, y as u32), "({}, {})", x, y);
+            }
+        }
+
+        // 24-bit BMP
+        let file = dir.path().join("colour.bmp");
+        let img = image::RgbImage::from_fn(6, 4, |x, y| match pattern(x, y) {
+            true => image::Rgb([0, 0, 0]),
+            false => image::Rgb([255, 255, 255]),
+        });
+        img.save(&file).unwrap();
+
+        let mut r = Render::new(RenderConfig{ y: 4, ..Default::default() });
+        assert_eq!(r.render_image(0, file.to_str().unwrap(), &ImageOptions::default()).unwrap(), 6);
+
+        for x in 0..6 {
+            for y in 0..4 {
+                assert_eq!(r.display.get(x, y).unwrap(), pattern(x as u32, y as u32), "({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn test_image_flip() {
+        let dir = tempdir::TempDir::new("ptouch").unwrap();
+        let file = dir.path().join("l.png");
+
+        // 'L' shaped pattern
+        let pattern = |x: u32, y: u32| x == 0 || y == 3;
+        let img = image::GrayImage::from_fn(3, 4, |x, y| match pattern(x, y) {
+            true => Luma([0u8]),
+            false => Luma([255u8]),
+        });
+        img.save(&file).unwrap();
+
+        let opts = ImageOptions{ flip_h: true, ..Default::default() };
+        let mut r = Render::new(RenderConfig{ y: 4, ..Default::default() });
+        assert_eq!(r.render_image(0, file.to_str().unwrap(), &opts).unwrap(), 3);
+
+        for x in 0..3 {
+            for y in 0..4 {
+                assert_eq!(r.display.get(x, y).unwrap(), pattern(2 - x as u32, y as u32), "({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn test_save() {
+        let dir = tempdir::TempDir::new("ptouch").unwrap();
+        let file = dir.path().join("render.png");
+
+        let mut r = Render::new(RenderConfig{ y: 16, ..Default::default() });
+        r.render(&[Op::text("Hi")]).unwrap();
+        r.save(&file).unwrap();
+
+        // Reload and compare pixels
+        let i = image::open(&file).unwrap().into_luma8();
+        let s = r.display.size();
+        assert_eq!(i.dimensions(), (s.width, s.height));
+
+        for x in 0..s.width {
+            for y in 0..s.height {
+                let p = i.get_pixel(x, y).0[0] < IMAGE_THRESHOLD;
+                assert_eq!(p, r.display.get(x as usize, y as usize).unwrap(), "({}, {})", x, y);
+            }
+        }
+
+        // I/O failures are returned
+        assert!(r.save(dir.path().join("missing/render.png")).is_err());
+    }
+
+    #[test]
+    fn test_save_bmp() {
+        let dir = tempdir::TempDir::new("ptouch").unwrap();
+        let file = dir.path().join("render.bmp");
+
+        let mut r = Render::new(RenderConfig{ y: 16, ..Default::default() });
+        r.render(&[Op::text("Hi")]).unwrap();
+        r.save_bmp(&file).unwrap();
+
+        let s = r.display.size();
+        let b = std::fs::read(&file).unwrap();
+        let u32_at = |i: usize| u32::from_le_bytes([b[i], b[i + 1], b[i + 2], b[i + 3]]);
+
+        // Header reports dimensions and bit depth
+        assert_eq!(&b[..2], b"BM");
+        assert_eq!(u32_at(2) as usize, b.len());
+        assert_eq!(u32_at(18), s.width);
+        assert_eq!(u32_at(22), s.height);
+        assert_eq!(u16::from_le_bytes([b[28], b[29]]), 1);
+
+        // Rows are padded to 4-byte boundaries
+        let stride = (s.width as usize).div_ceil(32) * 4;
+        assert_eq!(b.len() - u32_at(10) as usize, stride * s.height as usize);
+
+        // Decoded pixels match the display
+        let i = image::open(&file).unwrap().into_luma8();
+        for x in 0..s.width {
+            for y in 0..s.height {
+                let p = i.get_pixel(x, y).0[0] < IMAGE_THRESHOLD;
+                assert_eq!(p, r.display.get(x as usize, y as usize).unwrap(), "({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn test_bytes() {
+        let mut r = Render::new(RenderConfig{ y: 16, min_x: 4, ..Default::default() });
+        r.render(&[Op::text("Hi")]).unwrap();
+
+        let s = r.display.size();
+        let b = r.bytes().unwrap();
+        let stride = (s.width as usize).div_ceil(8);
+        assert_eq!(b.len(), stride * s.height as usize);
+
+        for x in 0..s.width as usize {
+            for y in 0..s.height as usize {
+                let p = b[y * stride + x / 8] & (1 << (x % 8)) != 0;
+                assert_eq!(p, r.display.get(x, y).unwrap(), "({}, {})", x, y);
+            }
+        }
+
+        // Short renders are padded to min_x
+        let r = Render::new(RenderConfig{ y: 8, min_x: 12, ..Default::default() });
+        assert_eq!(r.bytes().unwrap().len(), 2 * 8);
+    }
+
+    #[test]
+    fn test_trim() {
+        let mut r = Render::new(RenderConfig{ y: 16, min_x: 4, ..Default::default() });
+        r.render(&[Op::text("Hi"), Op::pad(32)]).unwrap();
+
+        // Find rightmost content column
+        let s = r.display.size();
+        let content = (0..s.width as usize)
+            .filter(|x| (0..s.height as usize).any(|y| r.display.get(*x, y).unwrap()))
+            .max().unwrap() + 1;
+        assert!(content < s.width as usize);
+
+        r.trim();
+        assert_eq!(r.display.size().width as usize, content);
+
+        // Blank renders are clamped to min_x
+        let mut r = Render::new(RenderConfig{ y: 16, min_x: 4, ..Default::default() });
+        r.render(&[Op::pad(32)]).unwrap();
+        r.trim();
+        assert_eq!(r.display.size().width, 4);
+    }
+
+    #[test]
+    fn test_too_wide() {
+        let mut r = Render::new(RenderConfig{ y: 16, min_x: 0, max_x: 64, ..Default::default() });
+        let res = r.render(&[Op::text("A very long label that does not fit")]);
+        assert!(matches!(res, Err(Error::TooWide{ max: 64, .. })), "{:?}", res.err());
+
+        let mut r = Render::new(RenderConfig{ y: 16, min_x: 0, max_x: 64, ..Default::default() });
+        r.render(&[Op::text("Hi")]).unwrap();
+    }
+
+    #[test]
+    fn test_justify() {
+        // Fetch the first column containing set pixels
+        let first = |r: &Render| {
+            let s = r.display.size();
+            (0..s.width as usize).find(|x| (0..s.height as usize).any(|y| r.display.get(*x, y).unwrap())).unwrap()
+        };
+
+        // Determine unpadded content width
+        let mut r = Render::new(RenderConfig{ y: 16, min_x: 0, ..Default::default() });
+        let width = r.render_text(0, "I", &TextOptions::default()).unwrap();
+        let left = first(&r);
+
+        let tests = &[
+            (HAlign::Left, left),
+            (HAlign::Centre, left + (64 - width) / 2),
+            (HAlign::Right, left + 64 - width),
+        ];
+
+        for (justify, x) in tests {
+            let mut r = Render::new(RenderConfig{ y: 16, min_x: 64, justify: *justify, ..Default::default() });
+            r.render(&[Op::text("I")]).unwrap();
+
+            assert_eq!(r.display.size().width, 64, "{:?}", justify);
+            assert_eq!(first(&r), *x, "{:?}", justify);
+        }
+    }
+
+    #[test]
+    fn test_config_with_media() {
+        let c = RenderConfig::default().with_media(&Media::Tze12mm);
+        assert_eq!(c.y, 70);
+
+        let c = RenderConfig::default().with_media(&Media::Hs24mm);
+        assert_eq!(c.y, 128);
+    }
+
+    #[test]
+    fn test_render_template() {
+        let vars: HashMap<String, String> = vec![
+            ("name".to_string(), "hello".to_string()),
+        ].into_iter().collect();
+
+        let render = |ops: &[Op]| {
+            let mut r = Render::new(RenderConfig::default());
+            r.render(ops).unwrap();
+            r.bytes().unwrap()
+        };
+
+        // Known variables are substituted
+        let mut r = Render::new(RenderConfig::default());
+        r.render_template(&[Op::text("{{ name }}")], &vars, true).unwrap();
+        assert_eq!(r.bytes().unwrap(), render(&[Op::text("hello")]));
+
+        // Unknown variables are left intact unless strict
+        let mut r = Render::new(RenderConfig::default());
+        r.render_template(&[Op::text("{{name}} {{other}}")], &vars, false).unwrap();
+        assert_eq!(r.bytes().unwrap(), render(&[Op::text("hello {{other}}")]));
+
+        let mut r = Render::new(RenderConfig::default());
+        let e = r.render_template(&[Op::text("{{name}} {{other}}")], &vars, true);
+        assert!(matches!(e, Err(Error::UnknownVariable(v)) if v == "other"));
+    }
+
+    #[test]
+    #[cfg(all(feature = "toml", feature = "serde"))]
+    fn test_config_from_toml() {
+        let d = tempdir::TempDir::new("ptouch").unwrap();
+        let f = d.path().join("config.toml");
+        std::fs::write(&f, "max_x = 512\njustify = \"centre\"\n").unwrap();
+
+        // Absent fields are defaulted
+        let c = RenderConfig::from_toml(&f).unwrap();
+        assert_eq!(c, RenderConfig{
+            max_x: 512,
+            justify: HAlign::Centre,
+            ..Default::default()
+        });
+
+        // Or retained from the existing configuration
+        let base = RenderConfig{ min_x: 100, y: 70, ..Default::default() };
+        let c = base.with_toml(&f).unwrap();
+        assert_eq!(c, RenderConfig{ min_x: 100, max_x: 512, y: 70, justify: HAlign::Centre });
+
+        // Invalid values are rejected
+        std::fs::write(&f, "y = \"tall\"\n").unwrap();
+        assert!(matches!(RenderConfig::from_toml(&f), Err(Error::Toml(_))));
+    }
+
+    #[test]
+    fn test_pad() {
+        let cfg = RenderConfig{ min_x: 0, y: 16, ..Default::default() };
+
+        // Padding alone reserves exactly the requested columns
+        let mut r = Render::new(cfg.clone());
+        r.render(&[Op::pad(10)]).unwrap();
+        assert_eq!(r.display.size().width, 10);
+
+        // Padding after content extends the display to `x + columns`, with added columns blank
+        let mut r = Render::new(cfg);
+        let x = r.render_text(0, "a", &TextOptions{ font: FontKind::Font6x8, ..Default::default() }).unwrap();
+        r.pad(x, 10).unwrap();
+
+        assert_eq!(r.display.size().width as usize, x + 10);
+        for c in x..x + 10 {
+            assert!((0..16).all(|y| !r.display.get(c, y).unwrap()), "column {} not blank", c);
+        }
+    }
+
+    #[test]
+    fn test_text_width_unicode() {
+        let opts = TextOptions::default();
+
+        // Multi-byte characters measure the same as single byte ones
+        assert_ne!("héllo".len(), "hello".len());
+        assert_eq!(opts.line_width("héllo"), opts.line_width("hello"));
+
+        let mut r = Render::new(RenderConfig::default());
+        let w = r.render_text(0, "héllo", &opts).unwrap();
+        assert_eq!(w, 5 * opts.font.char_width());
+    }
+
+    #[test]
+    fn test_text_empty() {
+        let tests = &[
+            ("", TextOptions::default()),
+            ("\n", TextOptions::default()),
+            ("\\n", TextOptions::default()),
+            ("", TextOptions{ wrap: true, ..Default::default() }),
+            ("\n", TextOptions{ fit: true, ..Default::default() }),
+            ("", TextOptions{ rotate: Rotation::Rotate90, ..Default::default() }),
+            ("", TextOptions{ vertical: true, ..Default::default() }),
+        ];
+
+        for (text, opts) in tests {
+            let mut r = Render::new(RenderConfig::default());
+            assert_eq!(r.render_text(0, text, opts).unwrap(), 0, "{:?} {:?}", text, opts);
+        }
+    }
+
+    #[test]
+    fn test_text_taller_than_label() {
+        let cfg = RenderConfig{ min_x: 0, y: 8, ..Default::default() };
+
+        for v_align in &[VAlign::Top, VAlign::Centre, VAlign::Bottom] {
+            let opts = TextOptions{ font: FontKind::Font6x12, h_align: HAlign::Left, v_align: *v_align, ..Default::default() };
+
+            let mut r = Render::new(cfg.clone());
+            r.render_text(0, "|", &opts).unwrap();
+
+            // Content is placed from the top of the label (y = 0), and clipped at the bottom
+            let mut expected = Render::new(cfg.clone());
+            expected.render_text(0, "|", &TextOptions{ v_align: VAlign::Top, ..opts.clone() }).unwrap();
+            assert_eq!(r.bytes().unwrap(), expected.bytes().unwrap(), "{:?}", v_align);
+            assert!(r.bytes().unwrap().iter().any(|b| *b != 0), "{:?}", v_align);
+        }
+    }
+
+    #[test]
+    fn test_text_advance_covers_glyphs() {
+        let tests = &[
+            // Wider fallback glyph at the end of a line
+            TextOptions{ font: FontKind::Font6x12, fallback: vec![FontKind::Font12x16], ..Default::default() },
+            // Italic overhang with tight letter spacing
+            TextOptions{ italic: true, letter_spacing: -2, ..Default::default() },
+            // Bold and scaled, right to left with a wide fallback glyph first
+            TextOptions{ font: FontKind::Font6x12, fallback: vec![FontKind::Font24x32], bold: true, scale: 2, rtl: true, ..Default::default() },
+        ];
+
+        for opts in tests {
+            for text in &["aé", "éa", "ab\néé"] {
+                let mut r = Render::new(RenderConfig{ min_x: 0, y: 128, ..Default::default() });
+                let w = r.render_text(0, text, &TextOptions{ h_align: HAlign::Left, ..opts.clone() }).unwrap();
+
+                // All drawn pixels lie within the returned advance
+                let (_, x1) = x_extent(&r, 0..128).unwrap();
+                assert!(x1 < w, "{:?} {:?}: pixel at {} beyond advance {}", text, opts, x1, w);
+            }
+        }
+    }
+
+    #[test]
+    fn test_text_trailing_newline() {
+        let render = |text| {
+            let mut r = Render::new(RenderConfig::default());
+            let w = r.render_text(0, text, &TextOptions::default()).unwrap();
+            (w, r.bytes().unwrap())
+        };
+
+        // A single trailing newline does not add a blank line
+        assert_eq!(render("A\n"), render("A"));
+        assert_eq!(render("A\nB\n"), render("A\nB"));
+
+        // Additional newlines are retained
+        assert_ne!(render("A\n\n"), render("A"));
+    }
+
+    #[test]
+    fn test_config_validate() {
+        assert_eq!(RenderConfig::new(32, 64, 16).unwrap(), RenderConfig{ min_x: 32, max_x: 64, y: 16, ..Default::default() });
+        assert!(RenderConfig::new(64, 64, 1).is_ok());
+
+        // Invalid combinations are rejected
+        assert!(matches!(RenderConfig::new(65, 64, 16), Err(Error::InvalidConfig(_))));
+        assert!(matches!(RenderConfig::new(32, 64, 0), Err(Error::InvalidConfig(_))));
+
+        // Including when constructed directly and rendered
+        let mut r = Render::new(RenderConfig{ min_x: 100, max_x: 10, ..Default::default() });
+        assert!(matches!(r.render(&[Op::text("hi")]), Err(Error::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn test_spacer() {
+        let opts = TextOptions{ h_align: HAlign::Left, ..Default::default() };
+        let w = opts.line_width("ab");
+
+        let mut r = Render::new(RenderConfig{ min_x: 0, ..Default::default() });
+        r.render(&[
+            Op::text_with_opts("ab", opts.clone()),
+            Op::spacer(20),
+            Op::text_with_opts("ab", opts),
+        ]).unwrap();
+
+        // Text is separated by exactly 20 blank columns
+        let y = r.display.size().height as usize;
+        let end = r.display.size().width as usize;
+        assert!(end > w + 20);
+        for x in w..w + 20 {
+            assert!((0..y).all(|y| !r.display.get(x, y).unwrap()), "column {} not blank", x);
+        }
+
+        // With the second text drawn identically to the first
+        for x in 0..end - w - 20 {
+            for y in 0..y {
+                assert_eq!(r.display.get(x, y).unwrap(), r.display.get(x + w + 20, y).unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn test_hline() {
+        let cfg = RenderConfig{ min_x: 0, y: 16, ..Default::default() };
+
+        let tests = &[
+            // Centred single pixel rule
+            (Op::hline(10), (4, 14), (7, 8)),
+            // Thick rule at a fixed row
+            (Op::HLine{ length: 6, y: Some(2), opts: LineOptions{ thickness: 3, ..Default::default() } }, (4, 10), (2, 5)),
+            // Clipped to the label height
+            (Op::HLine{ length: 6, y: Some(14), opts: LineOptions{ thickness: 4, ..Default::default() } }, (4, 10), (14, 16)),
+        ];
+
+        for (op, (x0, x1), (y0, y1)) in tests {
+            let mut r = Render::new(cfg.clone());
+            r.render(&[Op::pad(4), op.clone(), Op::pad(4)]).unwrap();
+
+            // Pixels are set only within the rule rectangle
+            for x in 0..r.display.size().width as usize {
+                for y in 0..16 {
+                    let inside = (*x0..*x1).contains(&x) && (*y0..*y1).contains(&y);
+                    assert_eq!(r.display.get(x, y).unwrap(), inside, "{:?} ({}, {})", op, x, y);
+                }
+            }
+
+            // Advancing by the rule length
+            assert_eq!(r.display.size().width as usize, x1 + 4);
+        }
+    }
+
+    #[test]
+    fn test_rect() {
+        let cfg = RenderConfig{ min_x: 0, y: 16, ..Default::default() };
+
+        let render = |op: Op| {
+            let mut r = Render::new(cfg.clone());
+            r.render(&[Op::pad(2), op, Op::pad(2)]).unwrap();
+            assert_eq!(r.display.size().width, 2 + 8 + 2);
+            r
+        };
+
+        // Filled rectangles set every pixel within the (centred) bounds
+        let r = render(Op::rect(8, 10, true));
+        for x in 0..12 {
+            for y in 0..16 {
+                let inside = (2..10).contains(&x) && (3..13).contains(&y);
+                assert_eq!(r.display.get(x, y).unwrap(), inside, "filled ({}, {})", x, y);
+            }
+        }
+
+        // Outlines set only the border, of the configured thickness
+        let r = render(Op::Rect{ width: 8, height: None, filled: false, opts: LineOptions{ thickness: 2, ..Default::default() } });
+        for x in 0..12 {
+            for y in 0..16 {
+                let inside = (2..10).contains(&x) && (0..16).contains(&y);
+                let hollow = (4..8).contains(&x) && (2..14).contains(&y);
+                assert_eq!(r.display.get(x, y).unwrap(), inside && !hollow, "outline ({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn test_line() {
+        let mut r = Render::new(RenderConfig{ min_x: 0, y: 16, ..Default::default() });
+        r.render(&[Op::pad(4), Op::line((0, 0), (9, 15)), Op::pad(4)]).unwrap();
+
+        // Endpoints are set, relative to the current position
+        assert!(r.display.get(4, 0).unwrap());
+        assert!(r.display.get(13, 15).unwrap());
+        assert!(!r.display.get(13, 0).unwrap());
+        assert!(!r.display.get(4, 15).unwrap());
+
+        // Advancing by the bounding width
+        assert_eq!(r.display.size().width, 4 + 10 + 4);
+        assert_eq!(count(&r), 16);
+    }
+
+    #[test]
+    fn test_circle() {
+        let mut r = Render::new(RenderConfig{ min_x: 0, y: 16, ..Default::default() });
+        r.render(&[Op::pad(2), Op::circle(9, true), Op::pad(2)]).unwrap();
+        assert_eq!(r.display.size().width, 2 + 9 + 2);
+
+        // Centre and cardinal edges are set
+        let (cx, cy) = (2 + 4, 8);
+        for (x, y) in &[(cx, cy), (cx - 4, cy), (cx + 4, cy), (cx, cy - 4), (cx, cy + 4)] {
+            assert!(r.display.get(*x, *y).unwrap(), "({}, {})", x, y);
+        }
+
+        // Outside the circle is not
+        for (x, y) in &[(cx - 4, cy - 4), (cx + 4, cy + 4), (cx, cy - 5), (cx, cy + 5), (cx - 5, cy)] {
+            assert!(!r.display.get(*x, *y).unwrap(), "({}, {})", x, y);
+        }
+
+        // Outlined circles leave the centre unset
+        let mut r = Render::new(RenderConfig{ min_x: 0, y: 16, ..Default::default() });
+        r.render(&[Op::circle(9, false)]).unwrap();
+        assert!(!r.display.get(4, cy).unwrap());
+        assert!(r.display.get(0, cy).unwrap());
+    }
+
+    #[test]
+    fn test_line_style() {
+        let render = |op: Op| {
+            let mut r = Render::new(RenderConfig{ min_x: 0, y: 16, ..Default::default() });
+            r.render(&[op]).unwrap();
+            count(&r)
+        };
+
+        let hline = |style, thickness| Op::HLine{ length: 24, y: Some(0), opts: LineOptions{ thickness, style } };
+        let line = |style| Op::Line{ from: (0, 0), to: (23, 0), opts: LineOptions{ thickness: 1, style } };
+
+        // Solid lines set every pixel, dashes are four on two off, and dots alternate
+        assert_eq!(render(hline(LineStyle::Solid, 1)), 24);
+        assert_eq!(render(hline(LineStyle::Dashed, 1)), 16);
+        assert_eq!(render(hline(LineStyle::Dotted, 1)), 12);
+        assert_eq!(render(line(LineStyle::Solid)), 24);
+        assert_eq!(render(line(LineStyle::Dashed)), 16);
+        assert_eq!(render(line(LineStyle::Dotted)), 12);
+
+        // Patterns scale with thickness
+        assert_eq!(render(hline(LineStyle::Dashed, 2)), 16 * 2);
+    }
+
+    #[test]
+    fn test_columns() {
+        let opts = TextOptions{ h_align: HAlign::Left, ..Default::default() };
+        let w = opts.line_width("ab");
+
+        let mut r = Render::new(RenderConfig{ min_x: 0, y: 24, ..Default::default() });
+        r.columns(&[
+            vec![Op::text_with_opts("ab", opts.clone())],
+            vec![Op::pad(4), Op::HLine{ length: 100, y: Some(0), opts: LineOptions::default() }],
+        ], &[40, 30]).unwrap();
+
+        // Total width is the sum of column widths
+        assert_eq!(r.display.size().width, 70);
+
+        // First column text is placed at the left edge, padded to the column width
+        let mut expected = Render::new(RenderConfig{ min_x: 0, y: 24, ..Default::default() });
+        expected.render(&[Op::text_with_opts("ab", opts)]).unwrap();
+        assert_eq!(x_extent(&r, 1..24), x_extent(&expected, 0..24));
+        assert!(x_extent(&r, 1..24).unwrap().1 < w);
+
+        // Second column starts at its offset and is clipped to its width
+        assert_eq!(x_extent(&r, 0..1), Some((44, 69)));
+
+        // Mismatched widths are rejected
+        assert!(matches!(r.columns(&[vec![]], &[]), Err(Error::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn test_rows() {
+        let mut r = Render::new(RenderConfig{ min_x: 0, y: 32, ..Default::default() });
+        r.rows(&[
+            vec![Op::HLine{ length: 20, y: None, opts: LineOptions::default() }],
+            vec![Op::HLine{ length: 10, y: None, opts: LineOptions{ thickness: 2, ..Default::default() } }],
+        ], &[10, 20]).unwrap();
+
+        // Rows are stacked with the whole block centred (offset 1), and each row centred in its band
+        let rows: Vec<usize> = (0..32).filter(|y| r.display.get(0, *y).unwrap()).collect();
+        assert_eq!(rows, vec![1 + 4, 1 + 10 + 9, 1 + 10 + 10]);
+
+        // With the label width set by the widest row
+        assert_eq!(r.display.size().width, 20);
+        assert_eq!(x_extent(&r, 20..22), Some((0, 9)));
+
+        // Rows must fit within the label
+        assert!(matches!(r.rows(&[vec![], vec![]], &[16, 17]), Err(Error::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn test_dimensions() {
+        let opts = TextOptions{ font: FontKind::Font6x8, ..Default::default() };
+
+        // Content is measured including padding and trailing glyph columns
+        let mut r = Render::new(RenderConfig{ min_x: 0, y: 16, ..Default::default() });
+        r.render(&[Op::pad(4), Op::text_with_opts("ab", opts.clone()), Op::pad(4)]).unwrap();
+        assert_eq!(r.dimensions(), Dimensions{ width: 4 + opts.line_width("ab") + 4, height: 16 });
+
+        // Or the minimum width if larger
+        let mut r = Render::new(RenderConfig{ min_x: 64, y: 16, ..Default::default() });
+        r.render(&[Op::text_with_opts("ab", opts)]).unwrap();
+        assert_eq!(r.dimensions(), Dimensions{ width: 64, height: 16 });
+    }
+
+    #[test]
+    fn test_text_options_builder() {
+        let opts = TextOptions::builder()
+            .font(FontKind::Font6x8)
+            .h_align(HAlign::Left)
+            .scale(2)
+            .bold(true)
+            .fallback(vec![FontKind::Font8x16])
+            .build();
+
+        // Unset fields retain defaults
+        assert_eq!(opts, TextOptions{
+            font: FontKind::Font6x8,
+            h_align: HAlign::Left,
+            scale: 2,
+            bold: true,
+            fallback: vec![FontKind::Font8x16],
+            ..Default::default()
+        });
+        assert_eq!(TextOptions::builder().build(), TextOptions::default());
+
+        // And render as equivalent options
+        let mut a = Render::new(RenderConfig::default());
+        a.render(&[Op::text_with_opts("hi", opts.clone())]).unwrap();
+
+        let mut b = Render::new(RenderConfig::default());
+        b.render(&[Op::Text{ text: "hi".to_string(), opts }]).unwrap();
+        assert_eq!(a.bytes().unwrap(), b.bytes().unwrap());
+        assert!(count(&a) > 0);
+    }
+
+    #[test]
+    fn test_text_superscript() {
+        let opts = TextOptions::builder().font(FontKind::Font12x16).h_align(HAlign::Left).build();
+        let sup = TextOptions::builder().font(FontKind::Font12x16).h_align(HAlign::Left).superscript().build();
+        let sub = TextOptions::builder().font(FontKind::Font12x16).h_align(HAlign::Left).subscript().build();
+
+        // Half size selects a smaller font
+        assert_eq!(sup.sized().font.char_height(), 8);
+
+        let y_extent = |opts: &TextOptions| {
+            let mut r = Render::new(RenderConfig{ min_x: 0, y: 32, ..Default::default() });
+            r.render_text(0, "8", opts).unwrap();
+
+            let rows: Vec<usize> = (0..32)
+                .filter(|y| (0..r.display.size().width as usize).any(|x| r.display.get(x, *y).unwrap()))
+                .collect();
+            (rows[0], rows[rows.len() - 1])
+        };
+
+        let (top, bottom) = y_extent(&opts);
+
+        // Superscripts sit above the normal baseline
+        let (sup_top, sup_bottom) = y_extent(&sup);
+        assert!(sup_bottom < bottom - 4, "{} {}", sup_bottom, bottom);
+        assert!(sup_top <= top, "{} {}", sup_top, top);
+
+        // Subscripts extend below it
+        let (sub_top, sub_bottom) = y_extent(&sub);
+        assert!(sub_top > top, "{} {}", sub_top, top);
+        assert!(sub_bottom > bottom, "{} {}", sub_bottom, bottom);
+    }
+
+    #[test]
+    fn test_text_runs() {
+        let small = TextOptions::builder().font(FontKind::Font6x8).build();
+        let large = TextOptions::builder().font(FontKind::Font12x16).bold(true).build();
+
+        let mut r = Render::new(RenderConfig{ min_x: 0, y: 32, ..Default::default() });
+        r.render(&[Op::runs(vec![
+            TextRun::new("ab", small.clone()),
+            TextRun::new("AB", large.clone()),
+        ])]).unwrap();
+
+        let (w0, w1) = (small.line_width("ab"), large.line_width("AB"));
+        assert_eq!(r.dimensions().width, w0 + w1);
+
+        // Each run matches the text rendered alone, aligned to the bottom of a shared 16px band
+        // centred in the label, such that both runs share a common baseline
+        for (x0, w, text, opts) in &[(0, w0, "ab", small), (w0, w1, "AB", large)] {
+            let mut e = Render::new(RenderConfig{ min_x: 0, y: 16, ..Default::default() });
+            e.render_text(0, text, &TextOptions{ v_align: VAlign::Bottom, h_align: HAlign::Left, ..opts.clone() }).unwrap();
+
+            for x in 0..*w {
+                for y in 0..16 {
+                    let expected = x < e.display.size().width as usize && e.display.get(x, y).unwrap();
+                    assert_eq!(r.display.get(x0 + x, 8 + y).unwrap(), expected, "{} ({}, {})", text, x, y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_text_markup() {
+        let render = |text, opts: TextOptions| {
+            let mut r = Render::new(RenderConfig::default());
+            let w = r.render_text(0, text, &opts).unwrap();
+            (w, r.bytes().unwrap())
+        };
+
+        let markup = TextOptions{ markup: true, ..Default::default() };
+
+        // Markers are removed and map to styles
+        assert_eq!(render("**Hi**", markup.clone()), render("Hi", TextOptions{ bold: true, ..Default::default() }));
+        assert_eq!(render("_Hi_", markup.clone()), render("Hi", TextOptions{ italic: true, ..Default::default() }));
+        assert_eq!(render("`Hi`", markup.clone()), render("Hi", TextOptions::default()));
+
+        // Partial styling differs from both plain and fully styled text
+        let partial = render("**H**i", markup.clone());
+        assert_ne!(partial, render("Hi", TextOptions::default()));
+        assert_ne!(partial, render("Hi", TextOptions{ bold: true, ..Default::default() }));
+
+        // Unmatched markers and markers within mono spans are literal
+        assert_eq!(render("snake_case", markup.clone()), render("snake_case", TextOptions::default()));
+        assert_eq!(render("`**a**`", markup.clone()), render("**a**", TextOptions::default()));
+
+        // Without markup enabled markers are literal
+        assert_ne!(render("**Hi**", TextOptions::default()), render("Hi", TextOptions{ bold: true, ..Default::default() }));
+    }
+
+    #[test]
+    fn test_text_markup_wrap() {
+        let cfg = RenderConfig{ min_x: 0, max_x: 40, ..Default::default() };
+        let render = |text, opts: &TextOptions| {
+            let mut r = Render::new(cfg.clone());
+            let w = r.render_text(0, text, opts).unwrap();
+            (w, r.bytes().unwrap())
+        };
+
+        let opts = TextOptions::builder()
+            .font(FontKind::Font6x8)
+            .h_align(HAlign::Left)
+            .markup(true)
+            .wrap(true)
+            .build();
+        let bold = TextOptions{ bold: true, markup: false, wrap: false, ..opts.clone() };
+
+        // Spans are wrapped as a whole, with no literal markers drawn on either line
+        assert_eq!(render("**two words**", &opts), render("two\nwords", &bold));
+
+        // Markers are not measured, so styled text wraps at the same point as plain text
+        assert_eq!(render("**ab cd**", &opts), render("ab cd", &bold));
+
+        // Truncated bold text fits within the maximum width including overhang
+        let opts = TextOptions{ wrap: false, max_width: Some(30), ellipsis: true, ..opts };
+        let (w, _) = render("**abcdefghij**", &opts);
+        assert!(w <= 30, "width: {}", w);
+        assert_eq!(render("**abcdefghij**", &opts), render("a...", &TextOptions{ max_width: None, ..bold }));
+    }
+
+    #[test]
+    fn test_text_line_align() {
+        let opts = TextOptions::builder()
+            .h_align(HAlign::Right)
+            .line_align(vec![HAlign::Centre, HAlign::Left])
+            .build();
+
+        let mut r = Render::new(RenderConfig{ min_x: 0, y: 64, ..Default::default() });
+        let w = r.render_text(0, "|\n|||||\n|", &opts).unwrap();
+        assert_eq!(w, opts.line_width("|||||"));
+
+        // Find the leftmost set pixel in each line
+        let h = opts.font.char_height() + opts.line_spacing;
+        let top = (64 - opts.text_height(3)) / 2;
+        let left = |line: usize| x_extent(&r, top + line * h..top + line * h + opts.font.char_height()).unwrap().0;
+
+        // Line 0 is centred, line 1 (full width) and line 2 fall back to right alignment
+        let pitch = opts.pitch();
+        assert_eq!(left(0), left(1) + 2 * pitch);
+        assert_eq!(left(2), left(1) + 4 * pitch);
+
+        // Line 1 is left aligned
+        let opts = TextOptions{ line_align: vec![HAlign::Centre, HAlign::Left], ..opts };
+        let mut r = Render::new(RenderConfig{ min_x: 0, y: 64, ..Default::default() });
+        r.render_text(0, "|||||\n|", &opts).unwrap();
+
+        let top = (64 - opts.text_height(2)) / 2;
+        let line0 = x_extent(&r, top..top + opts.font.char_height()).unwrap().0;
+        let line1 = x_extent(&r, top + h..top + h + opts.font.char_height()).unwrap().0;
+        assert_eq!(line0, line1);
+    }
+
+    #[test]
+    fn test_text_ellipsis() {
+        let opts = TextOptions::builder()
+            .font(FontKind::Font6x8)
+            .max_width(Some(60))
+            .ellipsis(true)
+            .build();
+
+        // Embedded fonts have no '…' glyph so "..." is used
+        assert_eq!(ellipsis(&opts), "...");
+
+        // Long lines are cut to fit with the ellipsis, short lines are unchanged
+        assert_eq!(truncate("the quick brown fox", 60, true, &opts), "the qui...");
+        assert_eq!(truncate("the quick brown fox", 60, false, &opts), "the quick");
+        assert_eq!(truncate("the quick", 60, true, &opts), "the quick");
+
+        // Rendered text matches the truncated string
+        let mut r = Render::new(RenderConfig{ min_x: 0, ..Default::default() });
+        let w = r.render_text(0, "the quick brown fox", &opts).unwrap();
+        assert!(w <= 60);
+
+        let mut expected = Render::new(RenderConfig{ min_x: 0, ..Default::default() });
+        expected.render_text(0, "the qui...", &TextOptions{ max_width: None, ..opts.clone() }).unwrap();
+        assert_eq!(r.display.image().unwrap(), expected.display.image().unwrap());
+
+        // Ellipsis also applies at the label width
+        let opts = TextOptions{ max_width: None, ..opts };
+        let mut r = Render::new(RenderConfig{ min_x: 0, max_x: 30, ..Default::default() });
+        assert_eq!(r.render_text(0, "the quick brown fox", &opts).unwrap(), opts.line_width("th..."));
+    }
+
+    #[test]
+    fn test_text_max_lines() {
+        let opts = TextOptions::builder()
+            .font(FontKind::Font6x8)
+            .wrap(true)
+            .max_lines(Some(2))
+            .build();
+
+        let text = "the quick brown fox jumps over the lazy dog again";
+        let cfg = RenderConfig{ min_x: 0, max_x: 60, ..Default::default() };
+        assert_eq!(wrap(text, 60, &opts).len(), 5);
+
+        // Only two lines are rendered, with the second ending in an ellipsis
+        let mut r = Render::new(cfg.clone());
+        r.render_text(0, text, &opts).unwrap();
+
+        let mut expected = Render::new(cfg.clone());
+        expected.render_text(0, "the quick\nbrown f...", &TextOptions{ max_lines: None, ..opts.clone() }).unwrap();
+        assert_eq!(r.display.image().unwrap(), expected.display.image().unwrap());
+
+        // Text within the limit is unchanged
+        let mut r = Render::new(cfg.clone());
+        r.render_text(0, "the quick", &opts).unwrap();
+
+        let mut expected = Render::new(cfg);
+        expected.render_text(0, "the quick", &TextOptions::builder().font(FontKind::Font6x8).build()).unwrap();
+        assert_eq!(r.display.image().unwrap(), expected.display.image().unwrap());
+    }
+}
@@ -4,14 +4,28 @@
 // https://github.com/ryankurte/rust-ptouch
 // Copyright 2021 Ryan Kurte
 
+use std::borrow::Cow;
+use std::collections::HashMap;
+
 use strum_macros::{Display, EnumString, EnumVariantNames};
 
+use crate::Error;
+
+use embedded_graphics::{
+    prelude::*,
+    pixelcolor::BinaryColor,
+    style::TextStyle,
+};
+
 #[cfg(feature = "serde")]
 use serde::{Serialize, Deserialize};
 
 #[cfg(feature = "structopt")]
 use structopt::StructOpt;
 
+#[cfg(feature = "structopt")]
+use strum::VariantNames;
+
 #[derive(Clone, Debug, PartialEq)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct RenderTemplate {
@@ -29,11 +43,31 @@ pub enum Op {
         #[cfg_attr(feature = "serde", serde(flatten))]
         opts: TextOptions
     },
+    /// Runs of differently styled text on a single line, sharing a common baseline
+    Runs{
+        runs: Vec<TextRun>,
+    },
+    /// Blank margin columns, typically at the start and end of a label.
+    /// This now inserts exactly `count` columns (rather than padding to a position)
+    /// and is an alias of [`Op::Spacer`], retained for compatibility with existing templates
     Pad{
         count: usize
     },
+    /// Fixed-width blank gap between operations, always advancing by exactly `width` columns
+    /// (equivalent to [`Op::Pad`], preferred for gaps within a layout)
+    Spacer{
+        width: usize
+    },
     Qr{
-        code: String
+        code: String,
+        #[cfg_attr(feature = "serde", serde(flatten, default))]
+        opts: QrOptions
+    },
+    #[cfg(feature = "datamatrix")]
+    DataMatrix{
+        code: String,
+        #[cfg_attr(feature = "serde", serde(flatten, default))]
+        opts: DataMatrixOptions
     },
     Barcode{
         code: String,
@@ -45,6 +79,40 @@ pub enum Op {
         #[cfg_attr(feature = "serde", serde(flatten, default))]
         opts: ImageOptions
     },
+    /// Horizontal rule `length` columns long, starting at row `y` or vertically centred if unset
+    HLine{
+        length: usize,
+        #[cfg_attr(feature = "serde", serde(default))]
+        y: Option<usize>,
+        #[cfg_attr(feature = "serde", serde(flatten, default))]
+        opts: LineOptions
+    },
+    /// Outlined (or filled) rectangle, vertically centred with the label height if `height` is unset
+    Rect{
+        width: usize,
+        #[cfg_attr(feature = "serde", serde(default))]
+        height: Option<usize>,
+        #[cfg_attr(feature = "serde", serde(default))]
+        filled: bool,
+        #[cfg_attr(feature = "serde", serde(flatten, default))]
+        opts: LineOptions
+    },
+    /// Line between `(x, y)` points, with X relative to the current position.
+    /// Advances by the bounding width of the line (the larger of the X coordinates plus one).
+    Line{
+        from: (usize, usize),
+        to: (usize, usize),
+        #[cfg_attr(feature = "serde", serde(flatten, default))]
+        opts: LineOptions
+    },
+    /// Outlined (or filled) circle, vertically centred and advancing by the diameter
+    Circle{
+        diameter: usize,
+        #[cfg_attr(feature = "serde", serde(default))]
+        filled: bool,
+        #[cfg_attr(feature = "serde", serde(flatten, default))]
+        opts: LineOptions
+    },
 }
 
 impl Op {
@@ -65,12 +133,71 @@ impl Op {
         }
     }
 
+    pub fn text_with_opts(s: &str, opts: TextOptions) -> Self {
+        Self::Text {
+            text: s.to_string(),
+            opts,
+        }
+    }
+
+    pub fn runs(runs: Vec<TextRun>) -> Self {
+        Self::Runs{ runs }
+    }
+
     pub fn pad(columns: usize) -> Self {
         Self::Pad{ count: columns }
     }
 
+    pub fn spacer(width: usize) -> Self {
+        Self::Spacer{ width }
+    }
+
+    pub fn hline(length: usize) -> Self {
+        Self::HLine{
+            length,
+            y: None,
+            opts: LineOptions::default(),
+        }
+    }
+
+    pub fn rect(width: usize, height: usize, filled: bool) -> Self {
+        Self::Rect{
+            width,
+            height: Some(height),
+            filled,
+            opts: LineOptions::default(),
+        }
+    }
+
+    pub fn line(from: (usize, usize), to: (usize, usize)) -> Self {
+        Self::Line{
+            from,
+            to,
+            opts: LineOptions::default(),
+        }
+    }
+
+    pub fn circle(diameter: usize, filled: bool) -> Self {
+        Self::Circle{
+            diameter,
+            filled,
+            opts: LineOptions::default(),
+        }
+    }
+
     pub fn qr(code: &str) -> Self {
-        Self::Qr{ code: code.to_string() }
+        Self::Qr{
+            code: code.to_string(),
+            opts: QrOptions::default(),
+        }
+    }
+
+    #[cfg(feature = "datamatrix")]
+    pub fn datamatrix(code: &str) -> Self {
+        Self::DataMatrix{
+            code: code.to_string(),
+            opts: DataMatrixOptions::default(),
+        }
     }
 
     pub fn barcode(code: &str) -> Self {
@@ -86,6 +213,61 @@ impl Op {
             opts: ImageOptions::default(),
         }
     }
+
+    /// Replace `{{name}}` placeholders in text and text run operations with values from `vars`,
+    /// see [`substitute`] for handling of unknown placeholders
+    pub fn substitute(&self, vars: &HashMap<String, String>, strict: bool) -> Result<Self, Error> {
+        let op = match self {
+            Self::Text{ text, opts } => Self::Text{
+                text: substitute(text, vars, strict)?,
+                opts: opts.clone(),
+            },
+            Self::Runs{ runs } => Self::Runs{
+                runs: runs.iter()
+                    .map(|r| Ok(TextRun{ text: substitute(&r.text, vars, strict)?, opts: r.opts.clone() }))
+                    .collect::<Result<_, Error>>()?,
+            },
+            _ => self.clone(),
+        };
+
+        Ok(op)
+    }
+}
+
+/// Serialize a list of render operations to JSON, for loading with [`load_ops`](super::load_ops)
+#[cfg(feature = "json")]
+pub fn to_json(ops: &[Op]) -> Result<String, Error> {
+    let s = serde_json::to_string_pretty(ops)?;
+    Ok(s)
+}
+
+/// Replace `{{name}}` placeholders in a string with values from `vars`.
+///
+/// Unknown placeholders are left intact, or return an error when `strict` is set.
+pub fn substitute(s: &str, vars: &HashMap<String, String>, strict: bool) -> Result<String, Error> {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+
+    while let Some(start) = rest.find("{{") {
+        let end = match rest[start..].find("}}") {
+            Some(e) => start + e + 2,
+            None => break,
+        };
+
+        out.push_str(&rest[..start]);
+
+        let name = rest[start + 2..end - 2].trim();
+        match (vars.get(name), strict) {
+            (Some(v), _) => out.push_str(v),
+            (None, false) => out.push_str(&rest[start..end]),
+            (None, true) => return Err(Error::UnknownVariable(name.to_string())),
+        }
+
+        rest = &rest[end..];
+    }
+
+    out.push_str(rest);
+    Ok(out)
 }
 
 
@@ -109,6 +291,16 @@ pub enum FontKind {
 }
 
 impl FontKind {
+    /// All embedded fonts
+    pub const ALL: [FontKind; 6] = [
+        FontKind::Font6x6,
+        FontKind::Font6x8,
+        FontKind::Font6x12,
+        FontKind::Font8x16,
+        FontKind::Font12x16,
+        FontKind::Font24x32,
+    ];
+
     pub fn char_width(&self) -> usize {
         use embedded_graphics::fonts::*;
 
@@ -134,8 +326,46 @@ impl FontKind {
             FontKind::Font24x32 => Font24x32::CHARACTER_SIZE.height as usize,
         }
     }
+
+    /// Check whether the font contains a glyph for the provided character
+    pub fn has_glyph(&self, c: char) -> bool {
+        use embedded_graphics::fonts::*;
+
+        // Unsupported characters are mapped to '?'
+        fn has<F: Font>(c: char) -> bool {
+            c == '?' || F::char_offset(c) != F::char_offset('?')
+        }
+
+        match self {
+            FontKind::Font6x6 => has::<Font6x6>(c),
+            FontKind::Font6x8 => has::<Font6x8>(c),
+            FontKind::Font6x12 => has::<Font6x12>(c),
+            FontKind::Font8x16 => has::<Font8x16>(c),
+            FontKind::Font12x16 => has::<Font12x16>(c),
+            FontKind::Font24x32 => has::<Font24x32>(c),
+        }
+    }
+
+    /// Draw a single line of text in this font with the top left corner at `p`
+    pub fn draw<D: DrawTarget<BinaryColor>>(&self, display: &mut D, value: &str, p: Point) -> Result<(), D::Error> {
+        use embedded_graphics::fonts::*;
+
+        let t = Text::new(value, p);
+
+        match self {
+            FontKind::Font6x6 => t.into_styled(TextStyle::new(Font6x6, BinaryColor::On)).draw(display),
+            FontKind::Font6x8 => t.into_styled(TextStyle::new(Font6x8, BinaryColor::On)).draw(display),
+            FontKind::Font6x12 => t.into_styled(TextStyle::new(Font6x12, BinaryColor::On)).draw(display),
+            FontKind::Font8x16 => t.into_styled(TextStyle::new(Font8x16, BinaryColor::On)).draw(display),
+            FontKind::Font12x16 => t.into_styled(TextStyle::new(Font12x16, BinaryColor::On)).draw(display),
+            FontKind::Font24x32 => t.into_styled(TextStyle::new(Font24x32, BinaryColor::On)).draw(display),
+        }
+    }
 }
 
+/// Italic slant, in rows per pixel of horizontal shear
+pub const ITALIC_SLANT: usize = 4;
+
 #[derive(Clone, PartialEq, Debug)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[cfg_attr(feature = "serde", serde(default))]
@@ -143,23 +373,81 @@ pub struct TextOptions {
     pub font: FontKind,
     pub v_align: VAlign,
     pub h_align: HAlign,
+    /// Integer scale factor for font glyphs
+    pub scale: usize,
+    /// Spacing between lines in pixels
+    pub line_spacing: usize,
+    /// Synthetic bold text
+    pub bold: bool,
+    /// Synthetic italic (sheared) text
+    pub italic: bool,
+    /// Underline text
+    pub underline: bool,
+    /// Strike through text
+    pub strikethrough: bool,
+    /// Additional spacing between characters in (unscaled) pixels,
+    /// negative values are limited to half the character width
+    pub letter_spacing: i32,
+    /// Wrap lines on whitespace to fit within the maximum label width
+    pub wrap: bool,
+    /// Override scale with the largest that fits the label height
+    pub fit: bool,
+    /// Rotate text within the label
+    pub rotate: Rotation,
+    /// Stack characters vertically, with each line of text forming a column
+    pub vertical: bool,
+    /// Lay out characters from right to left (note this does not perform shaping)
+    pub rtl: bool,
+    /// Fonts to use (in order) for characters missing from the primary font
+    pub fallback: Vec<FontKind>,
+    /// Invert text, drawing unset glyphs over a filled block
+    pub invert: bool,
+    /// Vertical offset as a fraction of the line height, positive values raise text (superscript)
+    pub baseline_shift: f32,
+    /// Relative text size, selecting the closest (not larger) available font and scale
+    pub size_scale: f32,
+    /// Maximum text width in pixels, longer lines are truncated
+    pub max_width: Option<usize>,
+    /// Append an ellipsis to truncated lines (those exceeding `max_width` or the label width)
+    pub ellipsis: bool,
+    /// Maximum number of lines (after wrapping), the last visible line of longer text ends with an ellipsis
+    pub max_lines: Option<usize>,
+    /// Per-line horizontal alignment, lines without an entry use `h_align`
+    pub line_align: Vec<HAlign>,
+    /// Parse inline `**bold**`, `_italic_` and `` `mono` `` markup
+    /// (embedded fonts are all monospaced, so mono spans only disable markup)
+    pub markup: bool,
 }
 
-#[derive(Clone, PartialEq, Debug)]
+/// Horizontal text alignment, applied to each line independently
+/// (capitalised names from earlier templates are accepted as aliases)
+#[derive(Copy, Clone, PartialEq, Debug, Display)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[cfg_attr(feature = "strum", derive(EnumString, EnumVariantNames))]
+#[cfg_attr(feature = "strum", strum(serialize_all = "snake_case"))]
+#[cfg_attr(feature = "serde", serde(rename_all="snake_case"))]
 pub enum HAlign {
+    #[cfg_attr(feature = "serde", serde(alias = "Left"))]
     Left,
+    #[cfg_attr(feature = "serde", serde(alias = "Centre"))]
     Centre,
+    #[cfg_attr(feature = "serde", serde(alias = "Right"))]
     Right,
 }
 
-#[derive(Clone, PartialEq, Debug)]
+/// Vertical text alignment, applied to the whole text block
+/// (capitalised names from earlier templates are accepted as aliases)
+#[derive(Copy, Clone, PartialEq, Debug, Display)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[cfg_attr(feature = "strum", derive(EnumString, EnumVariantNames))]
+#[cfg_attr(feature = "strum", strum(serialize_all = "snake_case"))]
+#[cfg_attr(feature = "serde", serde(rename_all="snake_case"))]
 pub enum VAlign {
+    #[cfg_attr(feature = "serde", serde(alias = "Top"))]
     Top,
+    #[cfg_attr(feature = "serde", serde(alias = "Centre"))]
     Centre,
+    #[cfg_attr(feature = "serde", serde(alias = "Bottom"))]
     Bottom,
 }
 
@@ -169,41 +457,697 @@ impl Default for TextOptions {
             font: FontKind::Font12x16,
             h_align: HAlign::Centre,
             v_align: VAlign::Centre,
+            scale: 1,
+            line_spacing: 4,
+            bold: false,
+            italic: false,
+            underline: false,
+            strikethrough: false,
+            letter_spacing: 0,
+            wrap: false,
+            fit: false,
+            rotate: Rotation::None,
+            vertical: false,
+            rtl: false,
+            fallback: vec![],
+            invert: false,
+            baseline_shift: 0.0,
+            size_scale: 1.0,
+            markup: false,
+            line_align: vec![],
+            max_width: None,
+            ellipsis: false,
+            max_lines: None,
+        }
+    }
+}
+
+/// Styled run of text, for mixing fonts and sizes within a line
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TextRun {
+    pub text: String,
+    #[cfg_attr(feature = "serde", serde(flatten, default))]
+    pub opts: TextOptions,
+}
+
+impl TextRun {
+    pub fn new(text: &str, opts: TextOptions) -> Self {
+        Self {
+            text: text.to_string(),
+            opts,
+        }
+    }
+}
+
+/// Builder for [`TextOptions`], starting from defaults
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct TextOptionsBuilder {
+    opts: TextOptions,
+}
+
+impl TextOptionsBuilder {
+    /// Set the primary font
+    pub fn font(mut self, font: FontKind) -> Self {
+        self.opts.font = font;
+        self
+    }
+
+    /// Set vertical alignment
+    pub fn v_align(mut self, v_align: VAlign) -> Self {
+        self.opts.v_align = v_align;
+        self
+    }
+
+    /// Set horizontal alignment
+    pub fn h_align(mut self, h_align: HAlign) -> Self {
+        self.opts.h_align = h_align;
+        self
+    }
+
+    /// Set the integer glyph scale factor
+    pub fn scale(mut self, scale: usize) -> Self {
+        self.opts.scale = scale;
+        self
+    }
+
+    /// Set spacing between lines in pixels
+    pub fn line_spacing(mut self, line_spacing: usize) -> Self {
+        self.opts.line_spacing = line_spacing;
+        self
+    }
+
+    /// Enable synthetic bold text
+    pub fn bold(mut self, bold: bool) -> Self {
+        self.opts.bold = bold;
+        self
+    }
+
+    /// Enable synthetic italic text
+    pub fn italic(mut self, italic: bool) -> Self {
+        self.opts.italic = italic;
+        self
+    }
+
+    /// Enable underline
+    pub fn underline(mut self, underline: bool) -> Self {
+        self.opts.underline = underline;
+        self
+    }
+
+    /// Enable strike through
+    pub fn strikethrough(mut self, strikethrough: bool) -> Self {
+        self.opts.strikethrough = strikethrough;
+        self
+    }
+
+    /// Set additional spacing between characters
+    pub fn letter_spacing(mut self, letter_spacing: i32) -> Self {
+        self.opts.letter_spacing = letter_spacing;
+        self
+    }
+
+    /// Enable wrapping to the label width
+    pub fn wrap(mut self, wrap: bool) -> Self {
+        self.opts.wrap = wrap;
+        self
+    }
+
+    /// Enable scaling to fit the label height
+    pub fn fit(mut self, fit: bool) -> Self {
+        self.opts.fit = fit;
+        self
+    }
+
+    /// Set text rotation
+    pub fn rotate(mut self, rotate: Rotation) -> Self {
+        self.opts.rotate = rotate;
+        self
+    }
+
+    /// Enable vertically stacked characters
+    pub fn vertical(mut self, vertical: bool) -> Self {
+        self.opts.vertical = vertical;
+        self
+    }
+
+    /// Enable right to left layout
+    pub fn rtl(mut self, rtl: bool) -> Self {
+        self.opts.rtl = rtl;
+        self
+    }
+
+    /// Set fallback fonts for missing glyphs
+    pub fn fallback(mut self, fallback: Vec<FontKind>) -> Self {
+        self.opts.fallback = fallback;
+        self
+    }
+
+    /// Enable inverted text
+    pub fn invert(mut self, invert: bool) -> Self {
+        self.opts.invert = invert;
+        self
+    }
+
+    /// Set the baseline shift as a fraction of line height
+    pub fn baseline_shift(mut self, baseline_shift: f32) -> Self {
+        self.opts.baseline_shift = baseline_shift;
+        self
+    }
+
+    /// Set the relative text size
+    pub fn size_scale(mut self, size_scale: f32) -> Self {
+        self.opts.size_scale = size_scale;
+        self
+    }
+
+    /// Set the maximum text width
+    pub fn max_width(mut self, max_width: Option<usize>) -> Self {
+        self.opts.max_width = max_width;
+        self
+    }
+
+    /// Enable ellipsis truncation
+    pub fn ellipsis(mut self, ellipsis: bool) -> Self {
+        self.opts.ellipsis = ellipsis;
+        self
+    }
+
+    /// Set the maximum number of lines
+    pub fn max_lines(mut self, max_lines: Option<usize>) -> Self {
+        self.opts.max_lines = max_lines;
+        self
+    }
+
+    /// Set per-line horizontal alignment
+    pub fn line_align(mut self, line_align: Vec<HAlign>) -> Self {
+        self.opts.line_align = line_align;
+        self
+    }
+
+    /// Enable inline markup
+    pub fn markup(mut self, markup: bool) -> Self {
+        self.opts.markup = markup;
+        self
+    }
+
+    /// Configure as a superscript, half size and raised by half a line
+    pub fn superscript(self) -> Self {
+        self.size_scale(0.5).baseline_shift(0.5)
+    }
+
+    /// Configure as a subscript, half size and lowered by a quarter line
+    pub fn subscript(self) -> Self {
+        self.size_scale(0.5).baseline_shift(-0.25)
+    }
+
+    /// Build text options
+    pub fn build(self) -> TextOptions {
+        self.opts
+    }
+}
+
+impl TextOptions {
+    /// Create a builder for text options
+    pub fn builder() -> TextOptionsBuilder {
+        TextOptionsBuilder::default()
+    }
+
+    /// Resolve `size_scale` to the tallest font and integer scale not exceeding the
+    /// scaled glyph height, preferring the primary font where heights are equal
+    pub fn sized(&self) -> TextOptions {
+        if self.size_scale == 1.0 {
+            return self.clone();
+        }
+
+        let target = (self.font.char_height() * self.scale) as f32 * self.size_scale;
+
+        let candidates = std::iter::once(self.font).chain(FontKind::ALL.iter().copied())
+            .flat_map(|f| (1..=self.scale.max(1)).map(move |s| (f, s)));
+
+        let mut best: Option<(FontKind, usize)> = None;
+        for (f, s) in candidates {
+            let h = f.char_height() * s;
+            let fits = h as f32 <= target;
+            let better = match best {
+                None => true,
+                Some((bf, bs)) => h > bf.char_height() * bs,
+            };
+
+            if fits && better {
+                best = Some((f, s));
+            }
+        }
+
+        // Fall back to the smallest font where nothing fits
+        let (font, scale) = best.unwrap_or((FontKind::Font6x6, 1));
+
+        TextOptions {
+            font,
+            scale,
+            size_scale: 1.0,
+            ..self.clone()
+        }
+    }
+
+    /// Apply additional bold / italic styles
+    pub fn styled(&self, bold: bool, italic: bool) -> Cow<'_, TextOptions> {
+        match (bold && !self.bold) || (italic && !self.italic) {
+            true => Cow::Owned(TextOptions {
+                bold: self.bold || bold,
+                italic: self.italic || italic,
+                ..self.clone()
+            }),
+            false => Cow::Borrowed(self),
+        }
+    }
+
+    /// Compute the rendered width of a single line of text
+    pub fn line_width(&self, line: &str) -> usize {
+        if line.is_empty() {
+            return 0;
+        }
+
+        // Bold glyphs overhang by one (unscaled) pixel
+        let mut overhang = match self.bold {
+            true => 1,
+            false => 0,
+        };
+
+        // Italic glyphs overhang by the shear at the top of the line
+        if self.italic {
+            overhang += self.shear(0);
+        }
+
+        // Characters advance by the primary font pitch, however fallback glyphs
+        // may be wider, so measure the extent of every glyph in drawing order
+        let chars: Vec<char> = match self.rtl {
+            true => line.chars().rev().collect(),
+            false => line.chars().collect(),
+        };
+
+        let extent = chars.iter().enumerate()
+            .map(|(j, c)| j * self.pitch() + self.glyph_font(*c).unwrap_or(self.font).char_width())
+            .max()
+            .unwrap_or(0);
+
+        (extent + overhang) * self.scale
+    }
+
+    /// Select the first font (primary then fallbacks) containing a glyph for the provided character
+    pub fn glyph_font(&self, c: char) -> Option<FontKind> {
+        std::iter::once(&self.font)
+            .chain(self.fallback.iter())
+            .find(|f| f.has_glyph(c))
+            .copied()
+    }
+
+    /// Compute the height of a block of text with the provided number of lines
+    pub fn text_height(&self, lines: usize) -> usize {
+        ((self.font.char_height() * self.scale + self.line_spacing) * lines).saturating_sub(self.line_spacing)
+    }
+
+    /// Compute the largest scale (minimum of 1) at which the provided number of lines fit within `height`
+    pub fn fit_scale(&self, lines: usize, height: usize) -> usize {
+        let available = ((height + self.line_spacing) / lines.max(1)).saturating_sub(self.line_spacing);
+
+        (available / self.font.char_height()).max(1)
+    }
+
+    /// Compute the (unscaled) horizontal distance between characters
+    pub fn pitch(&self) -> usize {
+        let w = self.font.char_width() as i32;
+
+        (w + self.letter_spacing).max(w / 2) as usize
+    }
+
+    /// Compute the thickness of text decorations (underline etc.)
+    pub fn decoration_thickness(&self) -> usize {
+        (self.font.char_height() * self.scale / 16).max(1)
+    }
+
+    /// Compute the italic shear for a given (unscaled) glyph row
+    pub fn shear(&self, y: usize) -> usize {
+        let baseline = self.font.char_height() - 1;
+
+        match self.italic {
+            true => baseline.saturating_sub(y) / ITALIC_SLANT,
+            false => 0,
         }
     }
 }
 
 #[derive(Clone, PartialEq, Debug)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct QrOptions {
+    /// Quiet zone around the code in modules (the QR specification recommends 4)
+    pub quiet_zone: usize,
+    /// Error correction level
+    pub ec_level: EcLevel,
+    /// Fixed symbol version (1-40), selected automatically if unset
+    pub version: Option<usize>,
+}
+
+impl QrOptions {
+    /// Compute the module size (minimum of 1) at which a symbol of `width` modules fits within `height`
+    pub fn module_size(&self, width: usize, height: usize) -> usize {
+        (height / (width + 2 * self.quiet_zone)).max(1)
+    }
+}
+
+impl Default for QrOptions {
+    fn default() -> Self {
+        Self {
+            quiet_zone: 2,
+            ec_level: EcLevel::M,
+            version: None,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct DataMatrixOptions {
+    /// Quiet zone around the symbol in modules (the Data Matrix specification requires 1)
+    pub quiet_zone: usize,
+}
+
+impl DataMatrixOptions {
+    /// Compute the module size (minimum of 1) at which a symbol of `width` modules fits within `height`
+    pub fn module_size(&self, width: usize, height: usize) -> usize {
+        (height / (width + 2 * self.quiet_zone)).max(1)
+    }
+}
+
+impl Default for DataMatrixOptions {
+    fn default() -> Self {
+        Self {
+            quiet_zone: 1,
+        }
+    }
+}
+
+/// QR error correction level, higher levels tolerate more damage at the cost of density
+#[derive(Copy, Clone, PartialEq, Debug, Display)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "strum", derive(EnumString, EnumVariantNames))]
+pub enum EcLevel {
+    /// Recovers ~7% of damaged data
+    L,
+    /// Recovers ~15% of damaged data
+    M,
+    /// Recovers ~25% of damaged data
+    Q,
+    /// Recovers ~30% of damaged data
+    H,
+}
+
+impl From<EcLevel> for qrcode::EcLevel {
+    fn from(l: EcLevel) -> Self {
+        match l {
+            EcLevel::L => qrcode::EcLevel::L,
+            EcLevel::M => qrcode::EcLevel::M,
+            EcLevel::Q => qrcode::EcLevel::Q,
+            EcLevel::H => qrcode::EcLevel::H,
+        }
+    }
+}
+
+/// Clockwise rotation
+#[derive(Copy, Clone, PartialEq, Debug, Display, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "strum", derive(EnumString, EnumVariantNames))]
+#[cfg_attr(feature = "serde", serde(rename_all="snake_case"))]
+pub enum Rotation {
+    #[cfg_attr(feature = "strum", strum(serialize = "0"))]
+    #[default]
+    None,
+    #[cfg_attr(feature = "strum", strum(serialize = "90"))]
+    Rotate90,
+    #[cfg_attr(feature = "strum", strum(serialize = "180"))]
+    Rotate180,
+    #[cfg_attr(feature = "strum", strum(serialize = "270"))]
+    Rotate270,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 #[cfg_attr(feature = "structopt", derive(StructOpt))]
 pub struct BarcodeOptions {
+    #[cfg_attr(feature = "structopt", structopt(long, possible_values = &Symbology::VARIANTS, default_value="code39"))]
+    /// Barcode symbology
+    pub symbology: Symbology,
+
     #[cfg_attr(feature = "structopt", structopt(default_value="4"))]
     /// Y offset from top and bottom of label
     pub y_offset: usize,
 
     #[cfg_attr(feature = "structopt", structopt(long))]
-    /// Double barcode width
+    /// Bar height in pixels, defaults to the label height less Y offsets
+    pub height: Option<usize>,
+
+    #[cfg_attr(feature = "structopt", structopt(long, default_value="1"))]
+    /// Width of a single barcode module in pixels
+    pub module_width: usize,
+
+    #[cfg_attr(feature = "structopt", structopt(long))]
+    /// Double barcode width (deprecated, use `module_width = 2`)
     pub double: bool,
+
+    #[cfg_attr(feature = "structopt", structopt(long))]
+    /// Append a check digit (mod-43 for Code39, Code128 always includes a checksum)
+    pub checksum: bool,
+
+    #[cfg_attr(feature = "structopt", structopt(long))]
+    /// Render the encoded value as text beneath the bars
+    pub human_readable: bool,
+
+    #[cfg_attr(feature = "structopt", structopt(long))]
+    /// Quiet zone either side of the bars in modules, defaults to the symbology minimum
+    pub quiet_zone: Option<usize>,
 }
 
 impl Default for BarcodeOptions {
     fn default() -> Self {
         Self {
+            symbology: Symbology::Code39,
             y_offset: 4,
+            height: None,
+            module_width: 1,
             double: false,
+            checksum: false,
+            human_readable: false,
+            quiet_zone: None,
+        }
+    }
+}
+
+impl BarcodeOptions {
+    /// Fetch the module width in pixels, with the deprecated `double` option
+    /// selecting a minimum width of 2
+    pub fn module_width(&self) -> usize {
+        match self.double {
+            true => self.module_width.max(2),
+            false => self.module_width,
+        }
+    }
+}
+
+/// Characters supported by Code39 symbology
+pub const CODE39_CHARS: &str = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ-. $/+%";
+
+/// Barcode symbology
+#[derive(Copy, Clone, PartialEq, Debug, Display)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "strum", derive(EnumString, EnumVariantNames))]
+#[cfg_attr(feature = "strum", strum(serialize_all = "snake_case"))]
+#[cfg_attr(feature = "serde", serde(rename_all="snake_case"))]
+pub enum Symbology {
+    /// Code 39, upper case alphanumerics and a few symbols
+    Code39,
+    /// Code 128 (using code set B), printable ASCII
+    Code128,
+    /// EAN-13 retail barcode, 12 digits plus check digit
+    Ean13,
+    /// EAN-8 retail barcode, 7 digits plus check digit
+    Ean8,
+    /// UPC-A retail barcode, 11 digits plus check digit
+    UpcA,
+    /// PDF417 stacked barcode, requires at least 9 modules of label height
+    Pdf417,
+}
+
+impl Symbology {
+    /// Check whether a character can be encoded with this symbology
+    pub fn is_valid(&self, c: char) -> bool {
+        match self {
+            Symbology::Code39 => CODE39_CHARS.contains(c),
+            Symbology::Code128 => (' '..='~').contains(&c),
+            Symbology::Ean13 | Symbology::Ean8 | Symbology::UpcA => c.is_ascii_digit(),
+            Symbology::Pdf417 => true,
+        }
+    }
+
+    /// Minimum quiet zone either side of the bars in modules
+    pub fn quiet_zone(&self) -> usize {
+        match self {
+            Symbology::Code39 | Symbology::Code128 => 10,
+            Symbology::Ean13 | Symbology::UpcA => 9,
+            Symbology::Ean8 => 7,
+            Symbology::Pdf417 => super::PDF417_QUIET_ZONE,
         }
     }
+
+    /// Check whether this is a retail (EAN/UPC) symbology, with guard bars and digits beneath
+    pub fn is_retail(&self) -> bool {
+        matches!(self, Symbology::Ean13 | Symbology::Ean8 | Symbology::UpcA)
+    }
 }
 
+/// Default luminance below which image pixels are set
+pub const IMAGE_THRESHOLD: u8 = 128;
+
 #[derive(Clone, PartialEq, Debug)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 #[cfg_attr(feature = "structopt", derive(StructOpt))]
 pub struct ImageOptions {
-    // TODO: scaling, invert, etc...
+    #[cfg_attr(feature = "structopt", structopt(long, possible_values = &Dither::VARIANTS, default_value="none"))]
+    /// Dithering applied when converting to 1-bit
+    pub dither: Dither,
+
+    #[cfg_attr(feature = "structopt", structopt(long, default_value="128"))]
+    /// Luminance below which pixels are set (ignored when dithering)
+    pub threshold: u8,
+
+    #[cfg_attr(feature = "structopt", structopt(long, possible_values = &ScaleMode::VARIANTS, default_value="fit"))]
+    /// Image scaling mode
+    pub scale_mode: ScaleMode,
+
+    #[cfg_attr(feature = "structopt", structopt(long))]
+    /// Target width in pixels for fill and stretch modes
+    pub width: Option<usize>,
+
+    #[cfg_attr(feature = "structopt", structopt(long))]
+    /// Target height in pixels, defaults to (and is limited by) the label height
+    pub height: Option<usize>,
+
+    #[cfg_attr(feature = "structopt", structopt(long, default_value="0", allow_hyphen_values = true))]
+    /// Brightness offset applied to luminance before conversion to 1-bit
+    pub brightness: i16,
+
+    #[cfg_attr(feature = "structopt", structopt(long, default_value="1.0"))]
+    /// Contrast multiplier applied to luminance (about mid-grey) before conversion to 1-bit
+    pub contrast: f32,
+
+    #[cfg_attr(feature = "structopt", structopt(long))]
+    /// Mirror the image horizontally
+    pub flip_h: bool,
+
+    #[cfg_attr(feature = "structopt", structopt(long))]
+    /// Mirror the image vertically
+    pub flip_v: bool,
+
+    #[cfg_attr(feature = "structopt", structopt(long, possible_values = &Rotation::VARIANTS, default_value="0"))]
+    /// Clockwise image rotation, applied after flipping and before scaling
+    pub rotate: Rotation,
 }
 
 impl Default for ImageOptions {
     fn default() -> Self {
-        Self {}
+        Self {
+            dither: Dither::None,
+            threshold: IMAGE_THRESHOLD,
+            scale_mode: ScaleMode::Fit,
+            width: None,
+            height: None,
+            brightness: 0,
+            contrast: 1.0,
+            flip_h: false,
+            flip_v: false,
+            rotate: Rotation::None,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct LineOptions {
+    /// Line thickness in pixels
+    pub thickness: usize,
+    /// Line style (applies to lines and rules)
+    pub style: LineStyle,
+}
+
+impl Default for LineOptions {
+    fn default() -> Self {
+        Self {
+            thickness: 1,
+            style: LineStyle::Solid,
+        }
     }
 }
+
+/// Line style, patterns are scaled by line thickness
+#[derive(Copy, Clone, PartialEq, Debug, Display, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "strum", derive(EnumString, EnumVariantNames))]
+#[cfg_attr(feature = "strum", strum(serialize_all = "snake_case"))]
+#[cfg_attr(feature = "serde", serde(rename_all="snake_case"))]
+pub enum LineStyle {
+    #[default]
+    Solid,
+    /// Alternating set and unset pixels
+    Dotted,
+    /// Dashes of four pixels separated by gaps of two
+    Dashed,
+}
+
+impl LineStyle {
+    /// Check whether the pixel at position `i` along a line of the provided thickness is set
+    pub fn is_set(&self, i: usize, thickness: usize) -> bool {
+        let i = i / thickness.max(1);
+
+        match self {
+            LineStyle::Solid => true,
+            LineStyle::Dotted => i.is_multiple_of(2),
+            LineStyle::Dashed => i % 6 < 4,
+        }
+    }
+}
+
+/// Image scaling mode
+#[derive(Copy, Clone, PartialEq, Debug, Display, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "strum", derive(EnumString, EnumVariantNames))]
+#[cfg_attr(feature = "strum", strum(serialize_all = "snake_case"))]
+#[cfg_attr(feature = "serde", serde(rename_all="snake_case"))]
+pub enum ScaleMode {
+    /// Scale to height preserving aspect ratio
+    #[default]
+    Fit,
+    /// Scale preserving aspect ratio to cover width and height, cropping overflow
+    Fill,
+    /// Scale to exact width and height
+    Stretch,
+}
+
+/// Image dithering mode
+#[derive(Copy, Clone, PartialEq, Debug, Display, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "strum", derive(EnumString, EnumVariantNames))]
+#[cfg_attr(feature = "strum", strum(serialize_all = "snake_case"))]
+#[cfg_attr(feature = "serde", serde(rename_all="snake_case"))]
+pub enum Dither {
+    /// Plain threshold
+    #[default]
+    None,
+    /// Floyd-Steinberg error diffusion
+    FloydSteinberg,
+    /// Atkinson error diffusion, higher contrast with less diffused error
+    Atkinson,
+}
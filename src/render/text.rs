@@ -0,0 +1,250 @@
+//! Text drawing helpers
+// Rust PTouch Driver / Utility
+//
+// https://github.com/ryankurte/rust-ptouch
+// Copyright 2021 Ryan Kurte
+
+use embedded_graphics::{
+    prelude::*,
+    pixelcolor::BinaryColor,
+};
+
+use super::TextOptions;
+
+/// DrawTarget adaptor applying glyph styles (scaling, bold, italic, invert) from TextOptions.
+/// Glyphs are drawn relative to the origin, styled, then written to the inner target.
+pub struct GlyphTarget<'a, D> {
+    inner: &'a mut D,
+    origin: Point,
+    opts: &'a TextOptions,
+}
+
+impl<'a, D> GlyphTarget<'a, D> {
+    /// Wrap a DrawTarget, drawing styled glyph pixels relative to `origin`
+    pub fn new(inner: &'a mut D, origin: Point, opts: &'a TextOptions) -> Self {
+        Self { inner, origin, opts }
+    }
+}
+
+impl<'a, D: DrawTarget<BinaryColor>> DrawTarget<BinaryColor> for GlyphTarget<'a, D> {
+    type Error = D::Error;
+
+    fn draw_pixel(&mut self, pixel: Pixel<BinaryColor>) -> Result<(), Self::Error> {
+        let Pixel(mut p, mut c) = pixel;
+        let f = self.opts.scale as i32;
+
+        if self.opts.invert {
+            c = c.invert();
+        }
+
+        // Synthetic italic by shearing rows relative to the baseline
+        p.x += self.opts.shear(p.y.max(0) as usize) as i32;
+
+        // Synthetic bold by also drawing the right-hand neighbour
+        let w = match self.opts.bold {
+            true => 2,
+            false => 1,
+        };
+
+        // Glyph pixels outside the target height are clipped
+        let height = self.inner.size().height as i32;
+
+        for x in p.x * f..(p.x + w) * f {
+            for y in p.y * f..(p.y + 1) * f {
+                let p = self.origin + Point::new(x, y);
+                if p.y < 0 || p.y >= height {
+                    continue;
+                }
+
+                self.inner.draw_pixel(Pixel(p, c))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn size(&self) -> Size {
+        self.inner.size()
+    }
+}
+
+/// Character with inline markup styles applied
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct StyledChar {
+    pub c: char,
+    pub bold: bool,
+    pub italic: bool,
+}
+
+impl StyledChar {
+    /// Create an unstyled character
+    pub fn plain(c: char) -> Self {
+        Self { c, bold: false, italic: false }
+    }
+}
+
+/// Parse a line of `**bold**`, `_italic_` and `` `mono` `` markup, markers without
+/// a matching close are treated as literal characters and markup is not parsed within mono spans
+pub fn parse_markup(line: &str) -> Vec<StyledChar> {
+    let mut out = vec![];
+    let (mut bold, mut italic, mut mono) = (false, false, false);
+
+    let mut i = 0;
+    while i < line.len() {
+        let rest = &line[i..];
+
+        // Toggle styles on markers, opening only where a close follows
+        let marker = ["`", "**", "_"].iter().find(|m| rest.starts_with(**m)).copied();
+        let state = match marker {
+            Some("`") => Some(&mut mono),
+            Some("**") if !mono => Some(&mut bold),
+            Some("_") if !mono => Some(&mut italic),
+            _ => None,
+        };
+
+        if let (Some(m), Some(s)) = (marker, state) {
+            if *s || rest[m.len()..].contains(m) {
+                *s = !*s;
+                i += m.len();
+                continue;
+            }
+        }
+
+        let c = rest.chars().next().unwrap();
+        out.push(StyledChar{ c, bold, italic });
+        i += c.len_utf8();
+    }
+
+    out
+}
+
+/// Convert a line of text to unstyled characters
+pub fn plain(line: &str) -> Vec<StyledChar> {
+    line.chars().map(StyledChar::plain).collect()
+}
+
+/// Strip styles from a line of styled characters
+pub fn unstyled(line: &[StyledChar]) -> String {
+    line.iter().map(|s| s.c).collect()
+}
+
+/// Compute the rendered width of a line of styled characters, allowing for bold and italic overhang
+pub fn styled_width(line: &[StyledChar], opts: &TextOptions) -> usize {
+    let bold = line.iter().any(|s| s.bold);
+    let italic = line.iter().any(|s| s.italic);
+
+    opts.styled(bold, italic).line_width(&unstyled(line))
+}
+
+/// Select an ellipsis, using '…' where available in the font (or fallbacks) and "..." otherwise
+pub fn ellipsis(opts: &TextOptions) -> &'static str {
+    match opts.glyph_font('…').is_some() {
+        true => "…",
+        false => "...",
+    }
+}
+
+/// Truncate a line of text to fit within `width` pixels, appending an ellipsis (if enabled)
+/// where characters are removed
+pub fn truncate(line: &str, width: usize, ellipsis: bool, opts: &TextOptions) -> String {
+    unstyled(&truncate_styled(&plain(line), width, ellipsis, opts))
+}
+
+/// Truncate a line of styled characters, see [`truncate`]
+pub fn truncate_styled(line: &[StyledChar], width: usize, ellipsis: bool, opts: &TextOptions) -> Vec<StyledChar> {
+    if styled_width(line, opts) <= width {
+        return line.to_vec();
+    }
+
+    match ellipsis {
+        true => cut(line, width, self::ellipsis(opts), opts),
+        false => cut(line, width, "", opts),
+    }
+}
+
+/// Append an ellipsis to a line of text, removing characters as required to fit within `width` pixels
+pub fn ellipsize(line: &str, width: usize, opts: &TextOptions) -> String {
+    unstyled(&ellipsize_styled(&plain(line), width, opts))
+}
+
+/// Append an ellipsis to a line of styled characters, see [`ellipsize`]
+pub fn ellipsize_styled(line: &[StyledChar], width: usize, opts: &TextOptions) -> Vec<StyledChar> {
+    cut(line, width, ellipsis(opts), opts)
+}
+
+/// Append characters to a line while the line and suffix fit within `width` pixels,
+/// the suffix takes the style of the last retained character
+fn cut(line: &[StyledChar], width: usize, suffix: &str, opts: &TextOptions) -> Vec<StyledChar> {
+    let with_suffix = |l: &[StyledChar]| {
+        let style = l.last().copied().unwrap_or_else(|| StyledChar::plain(' '));
+        let mut l = l.to_vec();
+        l.extend(suffix.chars().map(|c| StyledChar{ c, ..style }));
+        l
+    };
+
+    let mut current = vec![];
+    for s in line {
+        current.push(*s);
+        if styled_width(&with_suffix(&current), opts) > width {
+            current.pop();
+            break;
+        }
+    }
+
+    while current.last().map(|s| s.c.is_whitespace()).unwrap_or(false) {
+        current.pop();
+    }
+
+    with_suffix(&current)
+}
+
+/// Wrap a line of text on whitespace to fit within `width` pixels,
+/// breaking words wider than a line at character boundaries
+pub fn wrap(line: &str, width: usize, opts: &TextOptions) -> Vec<String> {
+    wrap_styled(&plain(line), width, opts).iter().map(|l| unstyled(l)).collect()
+}
+
+/// Wrap a line of styled characters, see [`wrap`]
+pub fn wrap_styled(line: &[StyledChar], width: usize, opts: &TextOptions) -> Vec<Vec<StyledChar>> {
+    let mut lines = vec![];
+    let mut current: Vec<StyledChar> = vec![];
+
+    for word in line.split(|s| s.c.is_whitespace()).filter(|w| !w.is_empty()) {
+        // Append words while they fit on the current line
+        let mut candidate = current.clone();
+        if !candidate.is_empty() {
+            candidate.push(StyledChar::plain(' '));
+        }
+        candidate.extend_from_slice(word);
+
+        if styled_width(&candidate, opts) <= width {
+            current = candidate;
+            continue;
+        }
+
+        // Otherwise start a new line
+        if !current.is_empty() {
+            lines.push(current);
+            current = vec![];
+        }
+
+        // Filling lines by character where the word is too wide
+        for s in word {
+            let mut candidate = current.clone();
+            candidate.push(*s);
+
+            if !current.is_empty() && styled_width(&candidate, opts) > width {
+                lines.push(current);
+                current = vec![*s];
+            } else {
+                current = candidate;
+            }
+        }
+    }
+
+    if !current.is_empty() || lines.is_empty() {
+        lines.push(current);
+    }
+
+    lines
+}
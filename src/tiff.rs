@@ -4,73 +4,45 @@
 // https://github.com/ryankurte/rust-ptouch
 // Copyright 2021 Ryan Kurte
 
-#[derive(Clone, Debug, PartialEq)]
-enum CompressMode {
-    None(u8),
-    Repeated(u8, usize),
-    Unique(Vec<u8>),
-}
+/// Maximum PackBits run length
+const MAX_RUN: usize = 128;
 
-// TODO: incomplete implementation, does not consider > 16 case from docs
+/// Compress a raster line using TIFF (PackBits) compression,
+/// falling back to a single literal run where this is not smaller
 pub fn compress(data: &[u8]) -> Vec<u8> {
     let mut c = Vec::<u8>::new();
+    let mut i = 0;
 
-    let mut state = CompressMode::None(data[0]);
-
-    // Perform byte-wise compression
-    for i in 1..data.len() {
-        state = match state {
-            CompressMode::None(v) if data[i] == v => CompressMode::Repeated(v, 1),
-            CompressMode::None(v) => CompressMode::Unique(vec![v, data[i]]),
-            CompressMode::Repeated(v, n) if data[i] == v => CompressMode::Repeated(v, n + 1),
-            CompressMode::Repeated(v, n) => {
-                let count = 0xFF - (n as u8 - 1);
-
-                c.push(count as u8);
-                c.push(v);
-
-                CompressMode::None(data[i])
-            }
-            CompressMode::Unique(mut v) if data[i] != v[v.len() - 1] => {
-                v.push(data[i]);
-
-                CompressMode::Unique(v)
-            }
-            CompressMode::Unique(v) => {
-                let count = v.len() - 1;
-
-                c.push(count as u8);
-                c.extend_from_slice(&v[..count]);
-
-                CompressMode::Repeated(data[i], 2)
-            }
-        };
-    }
-
-    // Finalize any pending data
-    match state {
-        CompressMode::None(v) => {
-            c.push(0x00);
-            c.push(v);
+    while i < data.len() {
+        // Measure repeated bytes from the current position
+        let mut run = 1;
+        while i + run < data.len() && data[i + run] == data[i] && run < MAX_RUN {
+            run += 1;
         }
-        CompressMode::Repeated(v, n) => {
-            let count = 0xFF - (n as u8 - 1);
 
-            c.push(count as u8);
-            c.push(v);
+        if run > 1 {
+            // -ve count for repeated bytes
+            c.push((1 - run as isize) as u8);
+            c.push(data[i]);
+            i += run;
+            continue;
         }
-        CompressMode::Unique(v) => {
-            let count = v.len() - 1;
 
-            c.push(count as u8);
-            c.extend_from_slice(&v);
+        // Collect literal bytes until the next repeat
+        let mut j = i + 1;
+        while j < data.len() && j - i < MAX_RUN && (j + 1 >= data.len() || data[j] != data[j + 1]) {
+            j += 1;
         }
+
+        // +ve count for literal bytes
+        c.push((j - i - 1) as u8);
+        c.extend_from_slice(&data[i..j]);
+        i = j;
     }
 
-    // If the encoded length > 16, just use this in simple mode.
-    if c.len() > 16 {
-        c = vec![];
-        c.push(data.len() as u8);
+    // Fall back to a literal run if compression is no smaller
+    if c.len() > data.len() + 1 {
+        c = vec![(data.len() - 1) as u8];
         c.extend_from_slice(data);
     }
 
@@ -101,7 +73,7 @@ pub fn uncompress(data: &[u8]) -> Vec<u8> {
         }
     }
 
-    return u
+    u
 }
 
 #[cfg(test)]
@@ -133,5 +105,24 @@ mod test {
         );
     }
 
-    // TODO: test compress / decompress as something is definitely not -right-
+    #[test]
+    fn test_raster_compression_roundtrip() {
+        let tests: &[&[u8]] = &[
+            &[0x00; 16],
+            &[0x01],
+            &[0x01, 0x02],
+            &[0x01, 0x01, 0x02, 0x03, 0x03, 0x03, 0x04],
+            &[0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f, 0x10],
+            &[0xff, 0x00, 0x00, 0xff, 0xff, 0x00, 0xff, 0x00, 0x00, 0x00, 0x12, 0x34, 0x34, 0x56, 0x78, 0x78],
+        ];
+
+        for t in tests {
+            let c = super::compress(t);
+            assert!(c.len() <= t.len() + 1, "Compressed: {:02x?} Input: {:02x?}", &c, t);
+            assert_eq!(&super::uncompress(&c), t, "Compressed: {:02x?}", &c);
+        }
+
+        // Repeated lines compress to a single run
+        assert_eq!(super::compress(&[0x00; 16]), vec![0xf1, 0x00]);
+    }
 }
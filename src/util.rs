@@ -3,14 +3,16 @@
 // https://github.com/ryankurte/rust-ptouch
 // Copyright 2021 Ryan Kurte
 
+use std::collections::HashMap;
+
 use log::{debug, warn};
 use simplelog::{LevelFilter, TermLogger, TerminalMode};
 use structopt::StructOpt;
 use strum::VariantNames;
 
-use ptouch::{Options, PTouch, render::RenderTemplate};
-use ptouch::device::{Media, PrintInfo};
-use ptouch::render::{FontKind, Op, Render, RenderConfig};
+use ptouch::{Options, PTouch, render::RenderTemplate, print::PrintOptions, tiff};
+use ptouch::device::{Media, PTouchDevice, Status};
+use ptouch::render::{FontKind, HAlign, Op, Render, RenderConfig, TextOptions, load_ops, list_fonts};
 
 
 #[derive(Clone, Debug, PartialEq, StructOpt)]
@@ -18,6 +20,12 @@ pub struct Flags {
     #[structopt(flatten)]
     options: Options,
 
+    #[structopt(flatten)]
+    print: PrintOptions,
+
+    #[structopt(flatten)]
+    render: RenderFlags,
+
     #[structopt(subcommand)]
     command: Command,
 
@@ -29,10 +37,46 @@ pub struct Flags {
     /// Default media kind when unable to query this from printer
     media: Media,
 
+    #[structopt(long)]
+    /// Render configuration file (TOML), overridden by render arguments
+    config: Option<String>,
+
+    #[structopt(long)]
+    /// Save rendered output to an image file (.png or .bmp)
+    output: Option<String>,
+
     #[structopt(long, default_value = "info")]
     log_level: LevelFilter,
 }
 
+/// Render configuration overrides
+#[derive(Clone, Debug, PartialEq, StructOpt)]
+pub struct RenderFlags {
+    #[structopt(long)]
+    /// Minimum label length in pixels
+    min_x: Option<usize>,
+
+    #[structopt(long)]
+    /// Maximum label length in pixels
+    max_x: Option<usize>,
+
+    #[structopt(long)]
+    /// Label height in pixels (defaults to the media print area)
+    y: Option<usize>,
+}
+
+impl RenderFlags {
+    /// Apply overrides to a render configuration
+    fn apply(&self, rc: RenderConfig) -> RenderConfig {
+        RenderConfig {
+            min_x: self.min_x.unwrap_or(rc.min_x),
+            max_x: self.max_x.unwrap_or(rc.max_x),
+            y: self.y.unwrap_or(rc.y),
+            ..rc
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, StructOpt)]
 pub enum RenderCommand {
     /// Basic text rendering
@@ -42,6 +86,10 @@ pub enum RenderCommand {
         #[structopt(long, possible_values = &FontKind::VARIANTS, default_value="12x16")]
         /// Text font
         font: FontKind,
+
+        #[structopt(long, possible_values = &HAlign::VARIANTS, default_value="centre")]
+        /// Horizontal text alignment
+        align: HAlign,
     },
     /// QR Code with text
     QrText {
@@ -54,6 +102,10 @@ pub enum RenderCommand {
         #[structopt(long, possible_values = &FontKind::VARIANTS, default_value="12x16")]
         /// Text font
         font: FontKind,
+
+        #[structopt(long, possible_values = &HAlign::VARIANTS, default_value="centre")]
+        /// Horizontal text alignment
+        align: HAlign,
     },
     /// QR Code
     Qr {
@@ -70,6 +122,12 @@ pub enum RenderCommand {
         /// Template file
         file: String,
     },
+    /// Render from JSON label definition
+    Json{
+        #[structopt(long)]
+        /// JSON file containing a list of render operations (or `-` to read from stdin)
+        file: String,
+    },
     /// Render from image
     Image{
         /// Image file
@@ -87,6 +145,13 @@ pub enum Command {
     // Fetch printer status
     Status,
 
+    // List available fonts
+    ListFonts{
+        #[structopt(long)]
+        /// Directory to search for `.ttf` / `.otf` font files (listed, but not yet supported for rendering)
+        font_dir: Option<String>,
+    },
+
     // Render and display a preview
     Preview(RenderCommand),
 
@@ -101,7 +166,40 @@ pub enum Command {
     },
 
     // Print data!
-    Print(RenderCommand),
+    Print{
+        #[structopt(long)]
+        /// Render and report print size without connecting to the printer
+        dry_run: bool,
+
+        #[structopt(subcommand)]
+        cmd: RenderCommand,
+    },
+
+    // Print a label for each CSV row, substituting columns into `{{column}}` text placeholders
+    Batch{
+        #[structopt(long)]
+        /// CSV file, with a header row naming each column
+        csv: String,
+
+        #[structopt(long)]
+        /// Column used to name rendered files (defaults to the first column)
+        key: Option<String>,
+
+        #[structopt(long)]
+        /// Error on placeholders without a matching column, rather than leaving these intact
+        strict: bool,
+
+        #[structopt(long)]
+        /// Render each row to `<key>.png` without connecting to the printer
+        dry_run: bool,
+
+        #[structopt(long, default_value=".")]
+        /// Output directory for dry run renders
+        out_dir: String,
+
+        #[structopt(subcommand)]
+        cmd: RenderCommand,
+    },
 }
 
 fn main() -> anyhow::Result<()> {
@@ -116,39 +214,36 @@ fn main() -> anyhow::Result<()> {
     )
     .unwrap();
 
-    // Create default render configuration
-    let mut rc = RenderConfig{
-        y: opts.media.area().1 as usize,
-        ..Default::default()
-    };
-
-    debug!("Connecting to PTouch device: {:?}", opts.options);
-
-    // Attempt to connect to ptouch device to inform configuration
-    let connect = match PTouch::new(&opts.options) {
-        Ok(mut pt) => {
-            debug!("Connected! fetching status...");
-
-            // Fetch device status
-            let status = pt.status()?;
-            debug!("Device status: {:?}", status);
-
-            // Build MediaWidth from status message to retrieve offsets
-            let media = Media::from((status.media_kind, status.media_width));
+    // List fonts without connecting to the printer
+    if let Command::ListFonts{ font_dir } = &opts.command {
+        for f in list_fonts(font_dir.as_deref().map(std::path::Path::new))? {
+            println!("{}", f);
+        }
+        return Ok(());
+    }
 
-            // Update render config to reflect tape
-            rc.y = media.area().1 as usize;
-            // TODO: update colours too?
-            
-            // Return device and mediat width
-            Ok((pt, status, media))
-        },
-        Err(e) => Err(e),
+    // Attempt to connect to ptouch device to inform configuration, unless this is a dry run
+    let connect = match &opts.command {
+        Command::Print{ dry_run: true, .. } | Command::Batch{ dry_run: true, .. } => Err(anyhow::anyhow!("Dry run")),
+        _ => connect(&opts.options).map_err(|e| anyhow::anyhow!("Error connecting to PTouch: {:?}", e)),
     };
 
+    // Update render config to reflect tape
+    let media = match &connect {
+        Ok((_, status)) => status.media(),
+        Err(_) => opts.media,
+    };
+    let rc = RenderConfig::default().with_media(&media);
+    // TODO: update colours too?
 
-    // TODO: allow RenderConfig override from CLI
+    // Load RenderConfig from file
+    let rc = match &opts.config {
+        Some(f) => rc.with_toml(f)?,
+        None => rc,
+    };
 
+    // Apply RenderConfig overrides from CLI
+    let rc = opts.render.apply(rc);
 
     // Run commands that do not _require_ the printer
     match &opts.command {
@@ -159,14 +254,9 @@ fn main() -> anyhow::Result<()> {
                 warn!("Using default media: {}, override with `--media` argument", opts.media);
             }
 
-            // Load render operations from command
-            let ops = cmd.load(opts.pad)?;
-            
-            // Create renderer
-            let mut r = Render::new(rc);
-
-            // Apply render operations
-            r.render(&ops)?;
+            // Render operations from command
+            let r = cmd.render(opts.pad, rc)?;
+            opts.save_output(&r)?;
 
             // Display render output
             r.show()?;
@@ -185,30 +275,51 @@ fn main() -> anyhow::Result<()> {
                 warn!("Using default media: {}, override with `--media` argument", opts.media);
             }
 
-            // Load render operations from command
-            let ops = cmd.load(opts.pad)?;
-            
-            // Create renderer
-            let mut r = Render::new(rc);
-
-            // Apply render operations
-            r.render(&ops)?;
+            // Render operations from command
+            let r = cmd.render(opts.pad, rc)?;
+            opts.save_output(&r)?;
 
             // Display render output
             r.save(file)?;
 
             return Ok(());
         },
+        Command::Print{ dry_run: true, cmd } => {
+            warn!("Dry run using media: {}, override with `--media` argument", opts.media);
+
+            // Render operations from command
+            let r = cmd.render(opts.pad, rc)?;
+            opts.save_output(&r)?;
+
+            let n = dry_run(&r, &media, opts.options.device, &opts.print)?;
+            let kind = match opts.print.compress {
+                true => "compressed",
+                false => "uncompressed",
+            };
+            println!("Print: {} bytes of {} raster data ({} copies)", n, kind, opts.print.copies);
+
+            return Ok(());
+        },
+        Command::Batch{ csv, key, strict, dry_run: true, out_dir, cmd } => {
+            warn!("Dry run using media: {}, override with `--media` argument", opts.media);
+
+            let (columns, rows) = load_rows(std::fs::File::open(csv)?)?;
+            let ops = cmd.load(opts.pad)?;
+
+            // Render each row to a file named by the key column
+            for (name, r) in batch_renders(&ops, &columns, &rows, key.as_deref(), *strict, rc)? {
+                let file = std::path::Path::new(out_dir).join(format!("{}.png", name));
+                r.save(&file)?;
+                println!("Rendered: {}", file.display());
+            }
+
+            return Ok(());
+        },
         _ => (),
     }
 
     // Check PTouch connection was successful
-    let (mut ptouch, status, media) = match connect {
-        Ok(d) => d,
-        Err(e) => {
-            return Err(anyhow::anyhow!("Error connecting to PTouch: {:?}", e));
-        }
-    };
+    let (mut ptouch, status) = connect?;
 
     // Run commands that -do- require the printer
     match &opts.command {
@@ -219,31 +330,25 @@ fn main() -> anyhow::Result<()> {
         Command::Status => {
             println!("Status: {:?}", status);
         },
-        Command::Print(cmd) => {
- 
-            // Load render operations from command
-            let ops = cmd.load(opts.pad)?;
-            
-            // Create renderer
-            let mut r = Render::new(rc);
-
-            // Apply render operations
-            r.render(&ops)?;
-
-            // Generate raster data for printing
-            let data = r.raster(media.area())?;
-            
-            // Setup print info based on media and rastered data
-            let info = PrintInfo {
-                width: Some(status.media_width),
-                length: Some(0),
-                raster_no: data.len() as u32,
-                ..Default::default()
-            };
+        Command::Print{ cmd, .. } => {
+            // Render operations from command
+            let r = cmd.render(opts.pad, rc)?;
+            opts.save_output(&r)?;
 
             // Print the thing!
-            ptouch.print_raw(data, &info)?;
+            ptouch.print(&r, &opts.print)?;
+
+        },
+        Command::Batch{ csv, key, strict, cmd, .. } => {
+            let (columns, rows) = load_rows(std::fs::File::open(csv)?)?;
+            let ops = cmd.load(opts.pad)?;
+
+            // Render labels for each row
+            let renders: Vec<_> = batch_renders(&ops, &columns, &rows, key.as_deref(), *strict, rc)?
+                .into_iter().map(|(_, r)| r).collect();
 
+            println!("Printing {} labels", renders.len());
+            ptouch.print_batch(&renders, &opts.print)?;
         },
         _ => (),
     }
@@ -253,23 +358,129 @@ fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
+impl Flags {
+    /// Save a render to the `--output` file if provided, inferring format from the extension
+    fn save_output(&self, r: &Render) -> Result<(), anyhow::Error> {
+        let file = match &self.output {
+            Some(f) => f,
+            None => return Ok(()),
+        };
+
+        let ext = std::path::Path::new(file).extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_lowercase());
+
+        match ext.as_deref() {
+            Some("png") => r.save(file),
+            Some("bmp") => r.save_bmp(file),
+            _ => Err(anyhow::anyhow!("Unsupported output file extension: {} (expected .png or .bmp)", file)),
+        }
+    }
+}
+
+/// Connect to a PTouch device and fetch the current status
+fn connect(o: &Options) -> Result<(PTouch, Status), ptouch::Error> {
+    debug!("Connecting to PTouch device: {:?}", o);
+
+    let mut pt = PTouch::new(o)?;
+
+    debug!("Connected! fetching status...");
+
+    // Fetch device status
+    let status = pt.status()?;
+    debug!("Device status: {:?}", status);
+
+    Ok((pt, status))
+}
+
+/// Check print options against the device and compute the raster data size
+/// (after compression, if enabled) for printing a render on the provided media
+fn dry_run(r: &Render, media: &Media, device: PTouchDevice, opts: &PrintOptions) -> Result<usize, anyhow::Error> {
+    opts.validate(device)?;
+
+    let (_, before, after) = opts.margins();
+    let blank = [0u8; 16];
+    let raster = r.raster(media.area())?;
+
+    let lines = std::iter::repeat_n(&blank, before)
+        .chain(raster.iter())
+        .chain(std::iter::repeat_n(&blank, after));
+
+    let n: usize = lines.map(|l| match opts.compress {
+            true => tiff::compress(l).len(),
+            false => l.len(),
+        })
+        .sum();
+
+    Ok(n * opts.copies)
+}
+
+/// CSV row, mapping column names to values
+type Row = HashMap<String, String>;
+
+/// Load CSV column names and rows
+fn load_rows<R: std::io::Read>(r: R) -> Result<(Vec<String>, Vec<Row>), anyhow::Error> {
+    let mut reader = csv::Reader::from_reader(r);
+    let columns: Vec<String> = reader.headers()?.iter().map(|c| c.to_string()).collect();
+
+    let mut rows = vec![];
+    for record in reader.records() {
+        let record = record?;
+        let row = columns.iter().cloned()
+            .zip(record.iter().map(|v| v.to_string()))
+            .collect();
+        rows.push(row);
+    }
+
+    Ok((columns, rows))
+}
+
+/// Render operations for each row, returning the key column value with each render
+fn batch_renders(ops: &[Op], columns: &[String], rows: &[Row], key: Option<&str>, strict: bool, rc: RenderConfig) -> Result<Vec<(String, Render)>, anyhow::Error> {
+    let key = match key.or_else(|| columns.first().map(|c| c.as_str())) {
+        Some(k) if columns.iter().any(|c| c == k) => k,
+        k => return Err(anyhow::anyhow!("Key column {:?} not found in CSV columns {:?}", k, columns)),
+    };
+
+    let mut renders = Vec::with_capacity(rows.len());
+    for row in rows {
+        let mut r = Render::new(rc.clone());
+        r.render_template(ops, row, strict)?;
+
+        // Strip path separators from names so these are usable as file names
+        let name = row[key].replace(['/', '\\'], "_");
+        renders.push((name, r));
+    }
+
+    Ok(renders)
+}
 
 impl RenderCommand {
+    /// Load and apply render operations
+    pub fn render(&self, pad: usize, rc: RenderConfig) -> Result<Render, anyhow::Error> {
+        let ops = self.load(pad)?;
+
+        let mut r = Render::new(rc);
+        r.render(&ops)?;
+
+        Ok(r)
+    }
+
     pub fn load(&self, pad: usize) -> Result<Vec<Op>, anyhow::Error> {
         match self {
-            RenderCommand::Text { text, font } => {
+            RenderCommand::Text { text, font, align } => {
                 let ops = vec![
                     Op::pad(pad),
-                    Op::text_with_font(text, *font),
+                    Op::text_with_opts(text, text_opts(*font, *align)),
                     Op::pad(pad),
                 ];
                 Ok(ops)
             },
-            RenderCommand::QrText { qr, text, font } => {
+            RenderCommand::QrText { qr, text, font, align } => {
                 let ops = vec![
                     Op::pad(pad),
                     Op::qr(qr),
-                    Op::text_with_font(text, *font), 
+                    Op::text_with_opts(text, text_opts(*font, *align)),
                     Op::pad(pad)
                 ];
                 Ok(ops)
@@ -298,6 +509,10 @@ impl RenderCommand {
                 // Return render operations
                 Ok(c.ops)
             },
+            RenderCommand::Json { file } => {
+                let ops = load_ops(file)?;
+                Ok(ops)
+            },
             RenderCommand::Image { file } => {
                 let ops = vec![
                     Op::pad(pad),
@@ -319,3 +534,123 @@ impl RenderCommand {
         }
     }
 }
+
+/// Build text options from CLI arguments
+fn text_opts(font: FontKind, h_align: HAlign) -> TextOptions {
+    TextOptions::builder()
+        .font(font)
+        .h_align(h_align)
+        .build()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_preview_args() {
+        let opts = Flags::from_iter(&["ptouch-util", "--min-x", "200", "--y", "32", "preview", "text", "hello"]);
+
+        let cmd = match &opts.command {
+            Command::Preview(cmd) => cmd,
+            c => panic!("Unexpected command: {:?}", c),
+        };
+
+        let rc = opts.render.apply(RenderConfig::default().with_media(&opts.media));
+        assert_eq!(rc.min_x, 200);
+        assert_eq!(rc.y, 32);
+        assert_eq!(rc.max_x, RenderConfig::default().max_x);
+
+        // Render without opening the preview window
+        let r = cmd.render(opts.pad, rc).unwrap();
+        assert!(r.bytes().unwrap().len() >= 200 / 8 * 32);
+    }
+
+    #[test]
+    fn test_print_dry_run() {
+        let opts = Flags::from_iter(&["ptouch-util", "--media", "tze24mm", "--copies", "3", "--no-auto-cut", "print", "--dry-run", "text", "hi"]);
+
+        let cmd = match &opts.command {
+            Command::Print{ dry_run: true, cmd } => cmd,
+            c => panic!("Unexpected command: {:?}", c),
+        };
+
+        assert_eq!(opts.print.copies, 3);
+        assert!(!opts.print.auto_cut);
+
+        let r = cmd.render(opts.pad, RenderConfig::default().with_media(&opts.media)).unwrap();
+        let lines = r.raster(opts.media.area()).unwrap().len();
+        let device = opts.options.device;
+        assert_eq!(dry_run(&r, &opts.media, device, &opts.print).unwrap(), lines * 16 * 3);
+
+        // Compressed size reflects the encoded raster data
+        let print = PrintOptions{ compress: true, copies: 1, ..opts.print.clone() };
+        let compressed: usize = r.raster(opts.media.area()).unwrap().iter().map(|l| tiff::compress(l).len()).sum();
+        assert_eq!(dry_run(&r, &opts.media, device, &print).unwrap(), compressed);
+        assert!(compressed < lines * 16);
+
+        // Options are validated against the device as for a real print
+        let print = PrintOptions{ copies: 0, ..opts.print.clone() };
+        assert!(dry_run(&r, &opts.media, device, &print).is_err());
+
+        let print = PrintOptions{ half_cut: true, ..opts.print.clone() };
+        assert!(dry_run(&r, &opts.media, PTouchDevice::PtP700, &print).is_err());
+    }
+
+    #[test]
+    fn test_output() {
+        let d = tempdir::TempDir::new("ptouch-util").unwrap();
+        let f = d.path().join("proof.png");
+
+        let opts = Flags::from_iter(&["ptouch-util", "--output", f.to_str().unwrap(), "print", "--dry-run", "text", "hi"]);
+        let cmd = match &opts.command {
+            Command::Print{ cmd, .. } => cmd,
+            c => panic!("Unexpected command: {:?}", c),
+        };
+
+        let r = cmd.render(opts.pad, RenderConfig::default().with_media(&opts.media)).unwrap();
+        opts.save_output(&r).unwrap();
+
+        // Image is one column per raster line, and the full configured height
+        let rc = RenderConfig::default().with_media(&opts.media);
+        let i = image::open(&f).unwrap().into_luma8();
+        assert_eq!(i.width() as usize, r.raster(opts.media.area()).unwrap().len());
+        assert_eq!(i.height() as usize, rc.y);
+
+        // Unsupported extensions are rejected
+        let opts = Flags::from_iter(&["ptouch-util", "--output", "proof.gif", "print", "text", "hi"]);
+        assert!(opts.save_output(&r).is_err());
+    }
+
+    #[test]
+    fn test_batch() {
+        let opts = Flags::from_iter(&["ptouch-util", "batch", "--csv", "tags.csv", "--key", "id", "--dry-run", "text", "{{name}} #{{id}}"]);
+        let cmd = match &opts.command {
+            Command::Batch{ cmd, .. } => cmd,
+            c => panic!("Unexpected command: {:?}", c),
+        };
+
+        let (columns, rows) = load_rows("name,id\nAlice,1\n\"Bob, Jr\",2\nCarol,3\n".as_bytes()).unwrap();
+        assert_eq!(columns, vec!["name", "id"]);
+        assert_eq!(rows.len(), 3);
+
+        // Placeholders are substituted per row
+        let ops = cmd.load(opts.pad).unwrap();
+        match &ops[1].substitute(&rows[1], false).unwrap() {
+            Op::Text{ text, .. } => assert_eq!(text, "Bob, Jr #2"),
+            o => panic!("Unexpected op: {:?}", o),
+        }
+
+        let rc = RenderConfig::default().with_media(&opts.media);
+        let renders = batch_renders(&ops, &columns, &rows, Some("id"), true, rc.clone()).unwrap();
+        let names: Vec<_> = renders.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(names, vec!["1", "2", "3"]);
+
+        // Unknown key columns are rejected
+        assert!(batch_renders(&ops, &columns, &rows, Some("missing"), false, rc.clone()).is_err());
+
+        // Strict mode rejects unknown placeholders
+        let ops = vec![Op::text("{{name}} {{missing}}")];
+        assert!(batch_renders(&ops, &columns, &rows, None, true, rc).is_err());
+    }
+}